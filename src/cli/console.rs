@@ -1,20 +1,29 @@
 use crate::utils::host_filtering::Blacklist;
-use crate::proxy::cache::HttpCache;
+use crate::proxy::cache::{now_secs, HttpCache};
+use crate::proxy::cache_stats::CacheCounters;
+use crate::proxy::config::ProxyConfig;
+use crate::proxy::invalidation::BanRule;
+use crate::proxy::store::{StoreBackend, StoreHealth, StoredEntry};
+use crate::proxy::warmup::warm_up;
+use crate::utils::stats::ProxyStats;
 use std::io;
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread;
 
-/// Module for adding commands to the manager console
-
+/// Hosts listed by the stats command
+const TOP_HOSTS: usize = 10;
 
-pub fn command_listener(blacklist: Arc<Blacklist>, cache: Arc<HttpCache>) {
+/// Module for adding commands to the manager console
+pub fn command_listener(blacklist: Arc<Blacklist>, cache: Arc<HttpCache>, stats: Arc<ProxyStats>, config: Arc<ProxyConfig>) {
     loop {
         print!("> "); // Show prompt
         io::stdout().flush().unwrap();
 
         let mut input = String::new();
         io::stdin().read_line(&mut input).unwrap();
-        let args: Vec<&str> = input.trim().split_whitespace().collect();
+        let args: Vec<&str> = input.split_whitespace().collect();
 
         if args.is_empty() {
             continue;
@@ -38,12 +47,287 @@ pub fn command_listener(blacklist: Arc<Blacklist>, cache: Arc<HttpCache>) {
                     Err(e) => println!("❌ Failed to clear cache: {}", e),
                 }
             },
+            "purge" => {
+                if args.len() > 1 {
+                    match cache.invalidate(args[1]) {
+                        Ok(true) => println!("✅ Purged {}", args[1]),
+                        Ok(false) => println!("🔹 {} is not in the cache", args[1]),
+                        Err(e) => println!("❌ Failed to purge {}: {}", args[1], e),
+                    }
+                }
+            },
+            "ban" => {
+                if args.len() < 3 {
+                    println!("❌ Usage: ban <host|prefix|regex> <value>");
+                    continue;
+                }
+                // A regex may contain spaces
+                let value = input.trim_start()[args[0].len()..].trim_start()[args[1].len()..].trim();
+                match BanRule::parse(args[1], value) {
+                    Ok(rule) => match cache.ban(&rule) {
+                        Ok(count) => println!("✅ Banned {}: {} entries removed", rule, count),
+                        Err(e) => println!("❌ Failed to ban {}: {}", rule, e),
+                    },
+                    Err(e) => println!("❌ {}", e),
+                }
+            },
+            "entries" => match cache.l1_entries() {
+                Ok(entries) if entries.is_empty() => println!("🔹 L1 cache is empty."),
+                Ok(entries) => {
+                    println!("💾 L1 entries ({}):", entries.len());
+                    for stored in entries {
+                        println!(
+                            " - {} ({} bytes, age {}s, {}, {} hits)",
+                            stored.key,
+                            stored.size,
+                            now_secs().saturating_sub(stored.entry.timestamp),
+                            describe_ttl(stored.entry.expires_at),
+                            stored.hits.map(|hits| hits.to_string()).unwrap_or_else(|| "?".to_string())
+                        );
+                    }
+                },
+                Err(e) => println!("❌ Failed to list L1 entries: {}", e),
+            },
+            "show" | "where" => {
+                if args.len() < 2 {
+                    println!("❌ Usage: {} <url>", args[0]);
+                    continue;
+                }
+                let mut found = false;
+                for tier in cache.locate(args[1]) {
+                    match tier.entry {
+                        Ok(Some(stored)) => {
+                            found = true;
+                            println!("💾 {} ({}): cached", tier.tier, tier.backend);
+                            if args[0] == "show" {
+                                print_entry(&stored);
+                            }
+                        },
+                        Ok(None) => println!("🔹 {} ({}): not cached", tier.tier, tier.backend),
+                        Err(e) => println!("❌ {} ({}): {}", tier.tier, tier.backend, e),
+                    }
+                }
+                if !found && args[0] == "show" {
+                    println!("🔹 {} is not in the cache", args[1]);
+                }
+            },
+            "keys" => {
+                let pattern = args.get(1).copied().unwrap_or("*");
+                match cache.l2_keys(pattern) {
+                    None => println!("🔹 No L2 cache configured"),
+                    Some(Ok(keys)) => {
+                        println!("💾 L2 keys matching {} ({}):", pattern, keys.len());
+                        for key in keys {
+                            println!(" - {}", key);
+                        }
+                    },
+                    Some(Err(e)) => println!("❌ Failed to list L2 keys: {}", e),
+                }
+            },
+            "export" | "import" => {
+                if args.len() < 2 {
+                    println!("❌ Usage: {} <file>", args[0]);
+                    continue;
+                }
+                let path = PathBuf::from(args[1]);
+                let result = if args[0] == "export" { cache.export(&path) } else { cache.import(&path) };
+                match result {
+                    Ok(report) => println!("📦 {}ed {}: {}", if args[0] == "export" { "Export" } else { "Import" }, path.display(), report),
+                    Err(e) => println!("❌ Failed to {} {}: {}", args[0], path.display(), e),
+                }
+            },
+            "warm" => {
+                if args.len() < 2 {
+                    println!("❌ Usage: warm <file> [concurrency]");
+                    continue;
+                }
+                let concurrency = match args.get(2).map(|value| value.parse::<usize>()) {
+                    None => config.warm_concurrency,
+                    Some(Ok(concurrency)) if concurrency > 0 => concurrency,
+                    Some(_) => {
+                        println!("❌ Invalid concurrency: {}", args[2]);
+                        continue;
+                    }
+                };
+
+                // Runs in the background, the report is printed when it is done
+                let path = PathBuf::from(args[1]);
+                let blacklist = Arc::clone(&blacklist);
+                let cache = Arc::clone(&cache);
+                let stats = Arc::clone(&stats);
+                let config = Arc::clone(&config);
+                thread::spawn(move || match warm_up(&path, concurrency, &blacklist, &cache, &stats, &config) {
+                    Ok(report) => println!("🔥 Warm-up finished: {}", report),
+                    Err(e) => println!("❌ Failed to read {}: {}", path.display(), e),
+                });
+            },
+            "offline" => {
+                match args.get(1).map(|value| value.to_lowercase()).as_deref() {
+                    None => {},
+                    Some("on") => {
+                        config.set_offline(true);
+                        cache.set_expiry_paused(true);
+                    },
+                    Some("off") => {
+                        config.set_offline(false);
+                        cache.set_expiry_paused(false);
+                    },
+                    Some(_) => {
+                        println!("❌ Usage: offline [on|off]");
+                        continue;
+                    }
+                }
+                if config.is_offline() {
+                    println!("📴 Offline mode on, requests are answered from the cache only and expiry is paused");
+                    let tiers = [Some(config.cache.l1_backend), config.cache.l2_backend];
+                    if tiers.contains(&Some(StoreBackend::Redis)) {
+                        println!(
+                            "⚠️ Redis still drops entries {}s after they expire (or after their own stale-if-error window)",
+                            config.cache.stale_grace_period
+                        );
+                    }
+                } else {
+                    println!("🌐 Offline mode off");
+                }
+            },
             "list" => blacklist.list_hosts(),
+            "stats" if args.len() > 1 => {
+                let host = args[1].to_lowercase();
+                match cache.stats().hosts.into_iter().find(|(cached_host, _)| cached_host.to_lowercase() == host) {
+                    Some((cached_host, counters)) => {
+                        println!("📊 Cache of {}:", cached_host);
+                        print_counters(&counters);
+                    },
+                    None => println!("🔹 No cache activity for {}", args[1]),
+                }
+            },
+            "stats" => {
+                stats.print_summary();
+                let cache_stats = cache.stats();
+                println!("📊 Cache:");
+                print_counters(&cache_stats.totals);
+                if !cache_stats.hosts.is_empty() {
+                    println!("📊 Busiest hosts:");
+                }
+                for (host, counters) in cache_stats.hosts.iter().take(TOP_HOSTS) {
+                    println!(
+                        " - {}: {} lookups, {:.0}% hits, {} stored, {} bytes served",
+                        host,
+                        counters.lookups(),
+                        counters.hit_ratio() * 100.0,
+                        counters.stores,
+                        counters.bytes_served
+                    );
+                }
+                for tier in cache.tier_stats() {
+                    println!("📊 {} cache ({}):", tier.tier, tier.backend);
+                    if let StoreHealth::Unhealthy { down_for, retry_in, error } = &tier.health {
+                        println!(
+                            " - ❌ unhealthy for {}s ({}), next reconnect in {}s",
+                            down_for.as_secs(), error, retry_in.as_secs()
+                        );
+                    }
+                    let stats = match tier.stats {
+                        Ok(stats) => stats,
+                        Err(e) => {
+                            println!(" - unavailable: {}", e);
+                            continue;
+                        }
+                    };
+                    match stats.bytes {
+                        Some(bytes) => println!(" - {} entries, {} bytes", stats.entries, bytes),
+                        None => println!(" - {} entries", stats.entries),
+                    }
+                    if tier.backend == "memory" {
+                        println!(
+                            " - evictions: {}, rejected by admission: {}, expired: {}",
+                            stats.evictions.evictions, stats.evictions.rejections, stats.evictions.expirations
+                        );
+                    }
+                }
+                let compression = cache.compression_stats();
+                if !compression.is_empty() {
+                    println!("📊 Compression:");
+                }
+                for (content_type, totals) in compression {
+                    println!(
+                        " - {}: {} compressed ({} -> {} bytes, {:.0}%), {} incompressible, {:.1}ms compressing, {} hits, {:.1}ms decompressing",
+                        content_type,
+                        totals.compressed,
+                        totals.bytes_in,
+                        totals.bytes_out,
+                        totals.ratio() * 100.0,
+                        totals.incompressible,
+                        totals.compress_time.as_secs_f64() * 1000.0,
+                        totals.decompressed,
+                        totals.decompress_time.as_secs_f64() * 1000.0
+                    );
+                }
+            },
             "exit" => {
                 println!("🔴 Exiting...");
                 break;
             }
-            _ => println!("❌ Unknown command. Use: add <host>, remove <host>, list, clear, exit"),
+            _ => println!("❌ Unknown command. Use: add <host>, remove <host>, list, clear, purge <url>, ban <host|prefix|regex> <value>, entries, show <url>, where <url>, keys [pattern], export <file>, import <file>, warm <file> [concurrency], offline [on|off], stats [host], exit"),
         }
     }
 }
+
+/// Prints cache counters of all hosts or of one
+fn print_counters(counters: &CacheCounters) {
+    println!(
+        " - lookups: {} ({:.0}% hits), L1 hits: {}, L2 hits: {}, misses: {}",
+        counters.lookups(),
+        counters.hit_ratio() * 100.0,
+        counters.l1_hits,
+        counters.l2_hits,
+        counters.misses
+    );
+    println!(" - stored: {}, rejected as uncacheable: {}", counters.stores, counters.rejects);
+    println!(
+        " - evictions: {}, promotions: {}, revalidations: {}",
+        counters.evictions, counters.promotions, counters.revalidations
+    );
+    println!(" - bytes served from cache: {}", counters.bytes_served);
+}
+
+/// Time until an entry expires, or since it did
+fn describe_ttl(expires_at: Option<u64>) -> String {
+    let now = now_secs();
+    match expires_at {
+        Some(expires_at) if expires_at > now => format!("expires in {}s", expires_at - now),
+        Some(expires_at) => format!("expired {}s ago", now - expires_at),
+        None => "no expiry".to_string(),
+    }
+}
+
+/// Prints the metadata and headers of an entry
+fn print_entry(stored: &StoredEntry) {
+    let entry = &stored.entry;
+    println!(" - size: {} bytes ({:?} compression)", stored.size, entry.compression);
+    println!(" - age: {}s, {}", now_secs().saturating_sub(entry.timestamp), describe_ttl(entry.expires_at));
+    if let Some(retain_until) = stored.retain_until {
+        println!(" - dropped in {}s", retain_until.saturating_sub(now_secs()));
+    }
+    if let Some(hits) = stored.hits {
+        println!(" - hits: {}", hits);
+    }
+    if let Some(etag) = &entry.etag {
+        println!(" - ETag: {}", etag);
+    }
+    if let Some(last_modified) = &entry.last_modified {
+        println!(" - Last-Modified: {}", last_modified);
+    }
+    println!(
+        " - stale-while-revalidate: {}, stale-if-error: {}, must-revalidate: {}",
+        entry.stale_while_revalidate.map(|seconds| format!("{}s", seconds)).unwrap_or_else(|| "-".to_string()),
+        entry.stale_if_error.map(|seconds| format!("{}s", seconds)).unwrap_or_else(|| "-".to_string()),
+        entry.must_revalidate
+    );
+    println!(" - headers:");
+    let mut headers: Vec<_> = entry.headers.iter().collect();
+    headers.sort();
+    for (name, value) in headers {
+        println!("     {}: {}", name, value);
+    }
+}
@@ -7,7 +7,24 @@ pub mod proxy {
     mod handler;
     mod http;
     mod https;
+    mod purge;
     pub mod cache;
+    pub mod cache_stats;
+    pub mod invalidation;
+    pub mod eviction;
+    pub mod l1;
+    pub mod maintenance;
+    pub mod store;
+    pub mod codec;
+    pub mod compression;
+    pub mod memory_store;
+    pub mod redis_store;
+    pub mod disk_store;
+    pub mod resilient_store;
+    pub mod snapshot;
+    pub mod config;
+    pub mod upstream;
+    pub mod warmup;
 }
 
 pub mod utils{
@@ -15,6 +32,8 @@ pub mod utils{
     pub mod parsing;
     pub mod host_filtering;
     pub mod responses;
+    pub mod stats;
+    pub mod block_page;
 }
 
 pub mod client {
@@ -4,16 +4,27 @@ use std::sync::Arc;
 
 use roxy::cli::console::command_listener;
 use roxy::proxy::listener::start_proxy;
+use roxy::proxy::warmup::warm_up;
 use roxy::utils::host_filtering::Blacklist;
-use roxy::proxy::cache::{HttpCache, CacheConfig};
+use roxy::proxy::cache::HttpCache;
+use roxy::proxy::config::ProxyConfig;
+use roxy::utils::stats::ProxyStats;
 
 fn main() {
+    // Read proxy configuration from the command line
+    let config = match ProxyConfig::from_args(std::env::args()) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
     // Initialize blacklist with default banned webpages
     let blacklist = Arc::new(Blacklist::new());
 
     // Initialize the cache
-    let cache_config = CacheConfig::default();
-    let cache = match HttpCache::new(cache_config) {
+    let cache = match HttpCache::new(config.cache.clone()) {
         Ok(cache) => Arc::new(cache),
         Err(e) => {
             eprintln!("Failed to initialize cache: {}", e);
@@ -21,19 +32,52 @@ fn main() {
         }
     };
 
+    // Started with --offline, the stores keep expired entries from the start
+    cache.set_expiry_paused(config.is_offline());
+
+    // Load a snapshot before serving, so the first requests already hit it
+    if let Some(path) = &config.import_from {
+        match cache.import(path) {
+            Ok(report) => println!("📦 Imported {}: {}", path.display(), report),
+            Err(e) => println!("❌ Failed to import {}: {}", path.display(), e),
+        }
+    }
+
+    // Aggregate statistics shared by the proxy and the console
+    let stats = Arc::new(ProxyStats::new());
+
     // Create clones for command listener thread
     let blacklist_clone_cmd = Arc::clone(&blacklist);
     let cache_clone_cmd = Arc::clone(&cache);
+    let stats_clone_cmd = Arc::clone(&stats);
+    let config_clone_cmd = Arc::clone(&config);
     // Create a thread for commands
-    let command_thread = thread::spawn(move || command_listener(blacklist_clone_cmd, cache_clone_cmd));
+    let command_thread = thread::spawn(move || command_listener(blacklist_clone_cmd, cache_clone_cmd, stats_clone_cmd, config_clone_cmd));
 
     // Create clones for proxy thread
     let blacklist_clone_proxy = Arc::clone(&blacklist);
     let cache_clone_proxy = Arc::clone(&cache);
+    let stats_clone_proxy = Arc::clone(&stats);
+    let config_clone_proxy = Arc::clone(&config);
     // Create a thread for proxy listener
-    let proxy_thread = thread::spawn(move || start_proxy(6505, blacklist_clone_proxy, cache_clone_proxy));
+    let proxy_thread = thread::spawn(move || start_proxy(blacklist_clone_proxy, cache_clone_proxy, stats_clone_proxy, config_clone_proxy));
+
+    // Warm up the cache in the background while the proxy already serves requests
+    if let Some(path) = config.warm_from.clone() {
+        let blacklist = Arc::clone(&blacklist);
+        let cache = Arc::clone(&cache);
+        let stats = Arc::clone(&stats);
+        let config = Arc::clone(&config);
+        thread::spawn(move || match warm_up(&path, config.warm_concurrency, &blacklist, &cache, &stats, &config) {
+            Ok(report) => println!("🔥 Warm-up finished: {}", report),
+            Err(e) => println!("❌ Failed to read {}: {}", path.display(), e),
+        });
+    }
 
     // Wait for proxy thread to finish (which it won't since it runs indefinitely)
     proxy_thread.join().unwrap();
     command_thread.join().unwrap();
+
+    // Stop the cache's background threads
+    cache.shutdown();
 }
@@ -1,14 +1,25 @@
-use std::collections::{HashMap, VecDeque};
-use std::thread;
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use redis::{Commands, Connection, RedisError};
 use serde::{Serialize, Deserialize};
 
-/// Handles cache it has 3 important structs:
+use crate::proxy::cache_stats::{CacheEvent, CacheStats, CacheStatsSnapshot};
+use crate::proxy::compression::{BodyCompression, CompressionConfig, CompressionTotals, Compressor};
+use crate::proxy::eviction::EvictionPolicy;
+use crate::proxy::invalidation::BanRule;
+use crate::proxy::maintenance::BackgroundWorker;
+use crate::proxy::redis_store::RedisConfig;
+use crate::proxy::resilient_store::ResilientStore;
+use crate::proxy::snapshot::{read_snapshot, write_snapshot, SnapshotReport, SnapshotTier};
+use crate::proxy::store::{CacheError, CacheStore, StoreBackend, StoreHealth, StoreStats, StoredEntry};
+use crate::utils::parsing::{add_headers, get_header, glob_match};
+
+// Handles cache it has 3 important structs:
 // CacheEntry - saved object
 // CacheConfig - config for HttpCache
 // HttpCache - handles objects that operate with data and some metadata to them
+// The tiers themselves are CacheStore implementations (see store.rs)
 
 
 
@@ -33,6 +44,22 @@ pub struct CacheEntry {
 
     /// Cache expiration time (None means no explicit expiration)
     pub expires_at: Option<u64>,
+
+    /// `stale-if-error` window in seconds from Cache-Control (if present)
+    #[serde(default)]
+    pub stale_if_error: Option<u64>,
+
+    /// `must-revalidate`/`proxy-revalidate` forbid serving the entry once stale
+    #[serde(default)]
+    pub must_revalidate: bool,
+
+    /// `stale-while-revalidate` window in seconds from Cache-Control (if present)
+    #[serde(default)]
+    pub stale_while_revalidate: Option<u64>,
+
+    /// Compression of `response_data` while stored (HttpCache returns entries decompressed)
+    #[serde(default)]
+    pub compression: BodyCompression,
 }
 
 impl CacheEntry {
@@ -46,13 +73,16 @@ impl CacheEntry {
         // Extract cache control directives
         let mut expires_at = None;
         let mut max_age = None;
-        let etag = headers.get("etag").cloned();
-        let last_modified = headers.get("last-modified").cloned();
+        let mut stale_if_error = None;
+        let mut stale_while_revalidate = None;
+        let mut must_revalidate = false;
+        let etag = get_header(&headers, "etag").cloned();
+        let last_modified = get_header(&headers, "last-modified").cloned();
 
         // Parse Cache-Control header
-        if let Some(cache_control) = headers.get("cache-control") {
+        if let Some(cache_control) = get_header(&headers, "cache-control").cloned() {
             for directive in cache_control.split(',') {
-                let directive = directive.trim();
+                let directive = directive.trim().to_lowercase();
                 if directive == "no-store" || directive == "no-cache" {
                     // Don't cache or validate on each use
                     return CacheEntry {
@@ -62,18 +92,35 @@ impl CacheEntry {
                         last_modified,
                         etag,
                         expires_at: Some(now), // Expire immediately
+                        stale_if_error: None,
+                        must_revalidate: true,
+                        stale_while_revalidate: None,
+                        compression: BodyCompression::None,
                     };
-                } else if directive.starts_with("max-age=") {
-                    if let Ok(seconds) = directive[8..].parse::<u64>() {
+                } else if let Some(value) = directive.strip_prefix("max-age=") {
+                    if let Ok(seconds) = value.parse::<u64>() {
                         max_age = Some(seconds);
                     }
+                } else if let Some(value) = directive.strip_prefix("stale-if-error=") {
+                    if let Ok(seconds) = value.parse::<u64>() {
+                        stale_if_error = Some(seconds);
+                    }
+                } else if let Some(value) = directive.strip_prefix("stale-while-revalidate=") {
+                    if let Ok(seconds) = value.parse::<u64>() {
+                        stale_while_revalidate = Some(seconds);
+                    }
+                } else if directive == "must-revalidate" || directive == "proxy-revalidate" {
+                    must_revalidate = true;
                 }
             }
         }
 
         // Parse Expires header if max-age wasn't specified
-        if max_age.is_none() {
-            if let Some(expires) = headers.get("expires") {
+        if let Some(max_age) = max_age {
+            // Use max-age if it was specified
+            expires_at = Some(now + max_age);
+        } else {
+            if let Some(expires) = get_header(&headers, "expires") {
 
                 // This is just a placeholder
                 if expires != "0" && !expires.is_empty() {
@@ -81,9 +128,6 @@ impl CacheEntry {
                     expires_at = Some(now + 5); // 20 seconds
                 }
             }
-        } else {
-            // Use max-age if it was specified
-            expires_at = Some(now + max_age.unwrap());
         }
 
         CacheEntry {
@@ -93,6 +137,26 @@ impl CacheEntry {
             last_modified,
             etag,
             expires_at,
+            stale_if_error,
+            must_revalidate,
+            stale_while_revalidate,
+            compression: BodyCompression::None,
+        }
+    }
+
+    /// Copy of the entry's metadata with an empty body
+    pub fn without_body(&self) -> CacheEntry {
+        CacheEntry {
+            response_data: Vec::new(),
+            headers: self.headers.clone(),
+            timestamp: self.timestamp,
+            last_modified: self.last_modified.clone(),
+            etag: self.etag.clone(),
+            expires_at: self.expires_at,
+            stale_if_error: self.stale_if_error,
+            must_revalidate: self.must_revalidate,
+            stale_while_revalidate: self.stale_while_revalidate,
+            compression: self.compression,
         }
     }
 
@@ -112,6 +176,43 @@ impl CacheEntry {
         true
     }
 
+    /// How long past expiry this entry may still be served when the origin fails
+    pub fn stale_window(&self, default_grace: u64) -> u64 {
+        if self.must_revalidate {
+            return 0;
+        }
+        self.stale_if_error.unwrap_or(default_grace)
+    }
+
+    /// Time until which the entry is kept (expiry plus the longer stale window), None if it never expires
+    pub fn retain_until(&self, default_grace: u64) -> Option<u64> {
+        let window = self.stale_window(default_grace).max(self.stale_while_revalidate.unwrap_or(0));
+        self.expires_at.map(|expires_at| expires_at + window)
+    }
+
+    /// Checks if an expired entry may be served while it is refreshed in the background
+    pub fn is_within_revalidate_window(&self) -> bool {
+        match (self.expires_at, self.stale_while_revalidate) {
+            (Some(expires_at), Some(window)) => now_secs() < expires_at + window,
+            _ => false,
+        }
+    }
+
+    /// Checks if an expired entry can still be served because the origin failed
+    ///
+    /// Only the stale-if-error window counts here, `stale-while-revalidate` extends retention but not this
+    pub fn is_usable_stale(&self, default_grace: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now_secs() < expires_at + self.stale_window(default_grace),
+            None => true,
+        }
+    }
+
+    /// Age of the entry in seconds, for the `Age` header
+    pub fn age(&self) -> u64 {
+        now_secs().saturating_sub(self.timestamp)
+    }
+
     /// Checks if this entry matches the conditional request headers
     pub fn matches_conditional_headers(&self, request_headers: &HashMap<String, String>) -> bool {
         // Check If-None-Match against ETag
@@ -135,9 +236,22 @@ impl CacheEntry {
 ///
 /// Configuration for the HTTP cache
 ///
+#[derive(Clone)]
 pub struct CacheConfig {
-    /// Maximum size of the in-memory (L1) cache
-    pub l1_max_size: usize,
+    /// Backend of the L1 tier
+    pub l1_backend: StoreBackend,
+
+    /// Backend of the L2 tier, None runs with L1 only
+    pub l2_backend: Option<StoreBackend>,
+
+    /// Maximum size of a memory store in bytes (bodies plus per-entry overhead)
+    pub l1_max_bytes: usize,
+
+    /// Largest response (in bytes) that is cached at all
+    pub max_object_size: usize,
+
+    /// Which entries a memory store drops when it is full
+    pub eviction_policy: EvictionPolicy,
 
     /// Default TTL for L1 cache entries (in seconds)
     pub l1_default_ttl: u64,
@@ -145,114 +259,305 @@ pub struct CacheConfig {
     /// Number of hits required to promote from L2 to L1
     pub promotion_threshold: usize,
 
-    /// Redis connection string
-    pub redis_url: String,
+    /// Promotions waiting for the promotion worker, more are dropped until it catches up
+    pub promotion_queue_size: usize,
+
+    /// Redis server and connection pool settings
+    pub redis: RedisConfig,
+
+    /// Compression of stored responses
+    pub compression: CompressionConfig,
+
+    /// Wait before the first reconnect attempt after L2 failed, doubled per failed attempt
+    pub l2_retry_min: Duration,
+
+    /// Longest wait between L2 reconnect attempts
+    pub l2_retry_max: Duration,
+
+    /// Directory of the disk store
+    pub disk_path: PathBuf,
+
+    /// Maximum size of the disk store in bytes, least recently used entries are evicted beyond it
+    pub disk_max_bytes: usize,
+
+    /// How long (in seconds) expired entries are kept to be served if the origin fails,
+    /// unless the response sets its own `stale-if-error`
+    pub stale_grace_period: u64,
 }
 
 impl Default for CacheConfig {
     fn default() -> Self {
         CacheConfig {
-            l1_max_size: 1000,
+            l1_backend: StoreBackend::Memory,
+            l2_backend: Some(StoreBackend::Redis),
+            l1_max_bytes: 64 * 1024 * 1024,
+            max_object_size: 8 * 1024 * 1024,
+            eviction_policy: EvictionPolicy::Lru,
             l1_default_ttl: 20,
             promotion_threshold: 5,
-            redis_url: "redis://127.0.0.1/".to_string(),
+            promotion_queue_size: 64,
+            redis: RedisConfig::default(),
+            compression: CompressionConfig::default(),
+            l2_retry_min: Duration::from_secs(1),
+            l2_retry_max: Duration::from_secs(60),
+            disk_path: PathBuf::from("cache"),
+            disk_max_bytes: 1024 * 1024 * 1024,
+            stale_grace_period: 300,
         }
     }
 }
 
+/// Statistics of one cache tier, for the console
+pub struct TierStats {
+    pub tier: &'static str,
+    pub backend: &'static str,
+    pub health: StoreHealth,
+    pub stats: Result<StoreStats, CacheError>,
+}
+
+/// A URL's entry in one cache tier, for the console
+pub struct TierEntry {
+    pub tier: &'static str,
+    pub backend: &'static str,
+
+    /// None if the tier doesn't hold the URL
+    pub entry: Result<Option<StoredEntry>, CacheError>,
+}
+
 ///
-/// A two-level HTTP cache with L1 (in-memory) and L2 (Redis) storage
+/// A two-level HTTP cache, L1 (in-memory by default) in front of an optional L2 (Redis by default)
 ///
 pub struct HttpCache {
-    /// L1 cache entries with access count for LRU implementation (not implemented)
-    l1_entries: Arc<RwLock<HashMap<String, (CacheEntry, usize)>>>,
+    /// Fast tier, entries are promoted to it from L2 after repeated hits
+    l1: Arc<dyn CacheStore>,
+
+    /// Tier every response is written to, None caches in L1 only
+    l2: Option<Arc<dyn CacheStore>>,
 
     /// Track hits for potential promotion from L2 to L1
     hit_counters: Arc<RwLock<HashMap<String, usize>>>,
 
-    /// Redis client for L2 (DB) caching
-    db_client: redis::Client,
-
     /// Cache configuration
     config: CacheConfig,
 
-    /// Queue for LRU eviction (not implemented, but ready)
-    lru_queue: Arc<Mutex<VecDeque<String>>>,
+    /// Keys with a background revalidation in progress
+    revalidating: Arc<Mutex<HashSet<String>>>,
+
+    /// Origin fetches in progress, used to collapse concurrent misses on the same key
+    in_flight: Arc<Mutex<HashMap<String, Arc<PendingFetch>>>>,
+
+    /// Copies hot L2 entries into L1
+    promotions: Arc<BackgroundWorker>,
+
+    /// Compresses stored responses, entries are decompressed before they are returned
+    compressor: Arc<Compressor>,
+
+    /// Hit, miss and store counters
+    stats: Arc<CacheStats>,
+}
+
+/// Result slot shared between the thread fetching a key and the threads waiting for it
+struct PendingFetch {
+    /// None while the fetch runs, then Some(entry) or Some(None) if nothing cacheable came back
+    result: Mutex<Option<Option<CacheEntry>>>,
+    ready: Condvar,
+}
+
+impl PendingFetch {
+    fn finish(&self, entry: Option<CacheEntry>) {
+        let mut result = match self.result.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if result.is_none() {
+            *result = Some(entry);
+            self.ready.notify_all();
+        }
+    }
+}
+
+/// What a thread that missed the cache should do
+pub enum FetchRole {
+    /// Fetch from the origin and publish the result
+    Leader(FetchLeader),
+    /// Wait for the leader's result
+    Follower(FetchFollower),
+}
+
+/// Held by the thread fetching a key from the origin; waiters are released on `complete` or drop
+pub struct FetchLeader {
+    key: String,
+    pending: Arc<PendingFetch>,
+    in_flight: Arc<Mutex<HashMap<String, Arc<PendingFetch>>>>,
+}
+
+impl FetchLeader {
+    /// Hands the fetched entry to the waiters (they fetch themselves if it isn't cacheable)
+    pub fn complete(self, entry: CacheEntry) {
+        let entry = if entry.is_valid() { Some(entry) } else { None };
+        self.pending.finish(entry);
+    }
+
+    /// Hands a stale copy to the waiters after the origin failed, so they don't all retry it
+    pub fn complete_stale(self, entry: CacheEntry) {
+        self.pending.finish(Some(entry));
+    }
+}
+
+impl Drop for FetchLeader {
+    fn drop(&mut self) {
+        // Releases waiters with no result if the fetch failed
+        self.pending.finish(None);
+
+        let mut in_flight = match self.in_flight.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        in_flight.remove(&self.key);
+    }
+}
+
+/// Handle for a thread waiting on another thread's fetch
+pub struct FetchFollower {
+    pending: Arc<PendingFetch>,
+}
+
+impl FetchFollower {
+    /// Waits for the leader's response
+    ///
+    /// - returns None if the response wasn't cacheable, the fetch failed or the wait timed out
+    pub fn wait(self, timeout: Duration) -> Option<CacheEntry> {
+        let result = match self.pending.result.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let (result, _) = match self.pending.ready.wait_timeout_while(result, timeout, |result| result.is_none()) {
+            Ok(waited) => waited,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        result.clone().flatten()
+    }
+}
+
+/// Marks a key as being revalidated, released when dropped
+pub struct RevalidationGuard {
+    key: String,
+    revalidating: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for RevalidationGuard {
+    fn drop(&mut self) {
+        let mut revalidating = match self.revalidating.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        revalidating.remove(&self.key);
+    }
 }
 
 impl HttpCache {
-    /// Creates a new HttpCache with the provided configuration
-    pub fn new(config: CacheConfig) -> Result<HttpCache, RedisError> {
-        let client = redis::Client::open(&config.redis_url[..])?;
+    /// Creates a new HttpCache with the stores chosen in the configuration
+    pub fn new(config: CacheConfig) -> Result<HttpCache, CacheError> {
+        let l1 = config.l1_backend.open(&config)?;
+        // L2 failures (also at startup) only disable L2 until it reconnects
+        let l2 = config
+            .l2_backend
+            .map(|backend| Arc::new(ResilientStore::open(backend, &config)) as Arc<dyn CacheStore>);
+        let promotions = BackgroundWorker::start("Promotion", config.promotion_queue_size);
+        let compressor = Compressor::new(config.compression.clone(), config.max_object_size);
+
+        // Evictions happen inside the stores, they report them back for the statistics
+        let stats = Arc::new(CacheStats::new());
+        for store in std::iter::once(&l1).chain(l2.iter()) {
+            let stats = stats.clone();
+            store.set_eviction_listener(Arc::new(move |url: &str| stats.record(url, CacheEvent::Eviction)));
+        }
 
-        // Test connection to Redis
-        let mut con = client.get_connection()?;
-        let _: String = con.ping()?;
+        println!(
+            "💾 Cache: L1 {}, L2 {}",
+            l1.name(),
+            l2.as_ref().map(|l2| l2.name()).unwrap_or("none")
+        );
 
         Ok(HttpCache {
-            l1_entries: Arc::new(RwLock::new(HashMap::new())),
+            l1,
+            l2,
             hit_counters: Arc::new(RwLock::new(HashMap::new())),
-            db_client: client,
             config,
-            lru_queue: Arc::new(Mutex::new(VecDeque::new())),
+            revalidating: Arc::new(Mutex::new(HashSet::new())),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            promotions: Arc::new(promotions),
+            compressor: Arc::new(compressor),
+            stats,
         })
     }
 
-    /// Get a connection to Redis
-    fn get_connection(&self) -> Result<Connection, RedisError> {
-        self.db_client.get_connection()
-    }
-
     /// Get an item from cache (either L1 or L2)
-    pub fn get(&self, host: &str, request_headers: &HashMap<String, String>) -> Option<CacheEntry> {
+    ///
+    /// Expired entries within their stale-while-revalidate window are returned too,
+    /// the caller should refresh them (see `begin_revalidation`)
+    pub fn get(&self, url: &str, request_headers: &HashMap<String, String>) -> Option<CacheEntry> {
+        let entry = self.lookup(url, request_headers);
+        if entry.is_none() {
+            self.stats.record(url, CacheEvent::Miss);
+        }
+        entry
+    }
 
+    /// Looks a URL up in L1, then L2, counting hits
+    fn lookup(&self, url: &str, request_headers: &HashMap<String, String>) -> Option<CacheEntry> {
         // First try L1 cache (fast path)
-        {
-            let mut l1_entries = match self.l1_entries.write() {
-                Ok(guard) => guard,
-                Err(poisoned) => poisoned.into_inner(),
-            };
-
-            if let Some((entry, access_count)) = l1_entries.get_mut(host) {
-                // Update access count
-                *access_count += 1;
-
+        match self.l1.get(url) {
+            // Compressed entries are inflated on the way out, a corrupt one counts as a miss
+            Ok(Some(entry)) => match self.compressor.decompress(entry) {
                 // Check if entry is still valid
-                if entry.is_valid() {
+                Some(entry) if entry.is_valid() => {
+                    self.stats.record(url, CacheEvent::L1Hit);
                     // Check if this is a conditional request
                     if entry.matches_conditional_headers(request_headers) {
-                        return Some(entry.clone());
+                        return Some(entry);
                     }
                     println!("Returning from L1");
-                    return Some(entry.clone());
-                } else {
-                    // Entry expired, remove from L1
-                    l1_entries.remove(host);
-                }
-            }
+                    return Some(entry);
+                },
+                Some(entry) if entry.is_within_revalidate_window() => {
+                    self.stats.record(url, CacheEvent::L1Hit);
+                    println!("Returning stale-while-revalidate entry from L1");
+                    return Some(entry);
+                },
+                _ => {},
+            },
+            Ok(None) => {},
+            Err(e) => println!("Error retrieving from L1 cache: {}", e),
         }
+
         // Try L2 cache
-        match self.get_from_l2(host) {
+        let l2 = self.l2.as_ref()?;
+        match l2.get(url) {
             Ok(Some(entry)) => {
                 // Check if entry is valid
-                if !entry.is_valid() {
+                if !entry.is_valid() && !entry.is_within_revalidate_window() {
                     return None;
                 }
+                let entry = self.compressor.decompress(entry)?;
+                self.stats.record(url, CacheEvent::L2Hit);
 
                 // Check if this is a conditional request
                 if entry.matches_conditional_headers(request_headers) {
                     // Update hit counter for potential promotion
-                    self.increment_hit_counter(host);
+                    self.increment_hit_counter(url);
                     println!("Returning from L2");
                     return Some(entry);
                 }
 
                 // Update hit counter for potential promotion
-                self.increment_hit_counter(host);
+                self.increment_hit_counter(url);
                 println!("Returning from L2");
-                Some(entry.clone())
+                Some(entry)
             },
             Ok(None) => None,
+            // Already reported when L2 went down
+            Err(CacheError::Unavailable(_)) => None,
             Err(e) => {
                 println!("Error retrieving from L2 cache: {}", e);
                 None
@@ -260,192 +565,409 @@ impl HttpCache {
         }
     }
 
-    /// Get an entry from the L2 (Redis) cache
-    fn get_from_l2(&self, host: &str) -> Result<Option<CacheEntry>, RedisError> {
-        let mut con = self.get_connection()?;
+    /// Registers a cache miss; the first thread for a key becomes the leader that fetches it,
+    /// later threads become followers that wait for its response
+    pub fn join_fetch(&self, url: &str) -> FetchRole {
+        let mut in_flight = match self.in_flight.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
 
-        match con.get::<_, Option<String>>(host) {
-            Ok(Some(serialized_entry)) => {
-                match serde_json::from_str(&serialized_entry) {
-                    Ok(entry) => {
-                        // println!("\n\n\nEntry {:?}\n\n\n", entry);
-                        Ok(Some(entry))
-                    },
-                    Err(e) => {
-                        println!("Error deserializing cache entry: {}", e);
-                        Ok(None)
+        if let Some(pending) = in_flight.get(url) {
+            return FetchRole::Follower(FetchFollower {
+                pending: Arc::clone(pending),
+            });
+        }
+
+        let pending = Arc::new(PendingFetch {
+            result: Mutex::new(None),
+            ready: Condvar::new(),
+        });
+        in_flight.insert(url.to_string(), Arc::clone(&pending));
+        FetchRole::Leader(FetchLeader {
+            key: url.to_string(),
+            pending,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Claims the background revalidation of a key
+    ///
+    /// - returns None if another thread is already revalidating it
+    pub fn begin_revalidation(&self, url: &str) -> Option<RevalidationGuard> {
+        let mut revalidating = match self.revalidating.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if !revalidating.insert(url.to_string()) {
+            return None;
+        }
+        self.stats.record(url, CacheEvent::Revalidation);
+        Some(RevalidationGuard {
+            key: url.to_string(),
+            revalidating: self.revalidating.clone(),
+        })
+    }
+
+    /// Refreshes an entry after the origin answered a revalidation with `304 Not Modified`
+    pub fn freshen(&self, url: &str, entry: CacheEntry, not_modified_headers: HashMap<String, String>) -> Result<(), CacheError> {
+        // The stored body keeps its own framing, whatever the 304 says about length
+        let updates: Vec<(String, String)> = not_modified_headers
+            .into_iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("content-length") && !name.eq_ignore_ascii_case("transfer-encoding"))
+            .collect();
+
+        let mut headers = entry.headers;
+        for (name, value) in &updates {
+            headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+            headers.insert(name.clone(), value.clone());
+        }
+        let updates: Vec<(&str, String)> = updates.iter().map(|(name, value)| (name.as_str(), value.clone())).collect();
+        let response_data = add_headers(&entry.response_data, &updates);
+        self.put(url, response_data, headers)
+    }
+
+    /// Get an entry that may be expired but is still within its stale window,
+    /// used when the origin is unreachable or answers with a 5xx
+    pub fn get_stale(&self, url: &str) -> Option<CacheEntry> {
+        let grace = self.config.stale_grace_period;
+        let tiers = std::iter::once(("L1", &self.l1)).chain(self.l2.iter().map(|l2| ("L2", l2)));
+
+        for (tier, store) in tiers {
+            match store.get(url) {
+                // must-revalidate forbids serving stale on error, even if retained for SWR
+                Ok(Some(entry)) if entry.must_revalidate && entry.expires_at.is_some_and(|at| now_secs() >= at) => {},
+                Ok(Some(entry)) if entry.is_usable_stale(grace) => {
+                    if let Some(entry) = self.compressor.decompress(entry) {
+                        println!("Returning stale entry from {}", tier);
+                        return Some(entry);
                     }
-                }
-            },
-            Ok(None) => Ok(None),
-            Err(e) => Err(e),
+                },
+                Ok(_) | Err(CacheError::Unavailable(_)) => {},
+                Err(e) => println!("Error retrieving from {} cache: {}", tier, e),
+            }
+        }
+        None
+    }
+
+    /// Get whatever entry the tiers still hold for a URL, fresh or not, for offline mode
+    ///
+    /// Expiry is paused in the memory and disk stores while offline (see `set_expiry_paused`),
+    /// only Redis still drops entries once their retention time has passed
+    pub fn get_offline(&self, url: &str) -> Option<CacheEntry> {
+        let tiers = std::iter::once((CacheEvent::L1Hit, &self.l1)).chain(self.l2.iter().map(|l2| (CacheEvent::L2Hit, l2)));
+
+        for (hit, store) in tiers {
+            match store.get(url) {
+                Ok(Some(entry)) => {
+                    if let Some(entry) = self.compressor.decompress(entry) {
+                        self.stats.record(url, hit);
+                        return Some(entry);
+                    }
+                },
+                Ok(None) | Err(CacheError::Unavailable(_)) => {},
+                Err(e) => println!("Error retrieving {} from cache: {}", url, e),
+            }
         }
+        self.stats.record(url, CacheEvent::Miss);
+        None
     }
 
-    /// Increment the hit counter for a host and check for promotion
-    fn increment_hit_counter(&self, host: &str) {
+    /// Increment the hit counter for a URL and check for promotion
+    fn increment_hit_counter(&self, url: &str) {
         let mut hit_counters = match self.hit_counters.write() {
             Ok(guard) => guard,
             Err(poisoned) => poisoned.into_inner(),
         };
 
-        let counter = hit_counters.entry(host.to_string()).or_insert(0);
+        let counter = hit_counters.entry(url.to_string()).or_insert(0);
         *counter += 1;
 
         // Check if it should be promoted to L1
         if *counter >= self.config.promotion_threshold {
-            hit_counters.remove(host);
-
-            // Clone host for the thread
-            let host_str = host.to_string();
+            // Clone the URL for the thread
+            let url_string = url.to_string();
             let self_clone = self.clone();
 
-            // Move to L1 asynchronously to not block the current request
-            thread::spawn(move || {
-                if let Ok(Some(entry)) = self_clone.get_from_l2(&host_str) {
-                    self_clone.put_l1(&host_str, entry);
-                }
-            });
+            // Move to L1 on the promotion worker to not block the current request;
+            // with the queue full the count is kept, so the next hit tries again
+            if self.promotions.submit(Box::new(move || self_clone.promote(&url_string))) {
+                hit_counters.remove(url);
+            }
+        }
+    }
+
+    /// Copies an entry from L2 to L1
+    fn promote(&self, url: &str) {
+        let l2 = match &self.l2 {
+            Some(l2) => l2,
+            None => return,
+        };
+        match l2.get(url) {
+            Ok(Some(entry)) => {
+                self.put_l1(url, &entry);
+                self.stats.record(url, CacheEvent::Promotion);
+            },
+            Ok(None) | Err(CacheError::Unavailable(_)) => {},
+            Err(e) => println!("Failed to promote {}: {}", url, e),
         }
     }
 
-    /// Store a response in both L1 and L2 caches
-    pub fn put(&self, host: &str, response_data: Vec<u8>, headers: HashMap<String, String>) -> Result<(), RedisError> {
+    /// Store a response in L2, or in L1 when there is no L2
+    pub fn put(&self, url: &str, response_data: Vec<u8>, headers: HashMap<String, String>) -> Result<(), CacheError> {
         println!("Adding to cache...");
         let entry = CacheEntry::new(response_data, headers);
 
         // Skip caching if the entry is immediately expired
         if !entry.is_valid() {
+            self.stats.record(url, CacheEvent::Reject);
             return Ok(());
         }
 
-        // Store in cache
-        self.put_l2(host, entry.clone())?;
-        // self.put_l1(host, entry);
-
-        Ok(())
-    }
-
-    /// Store a response in L2 (Redis) cache
-    fn put_l2(&self, host: &str, entry: CacheEntry) -> Result<(), RedisError> {
-        let mut con = self.get_connection()?;
+        // Skip objects that are too large to cache
+        if entry.response_data.len() > self.config.max_object_size {
+            self.reject_oversized(url);
+            return Ok(());
+        }
+        let entry = self.compressor.compress(entry);
 
-        // Serialize the entry
-        let serialized_entry = match serde_json::to_string(&entry) {
-            Ok(s) => s,
-            Err(e) => {
-                println!("Error serializing cache entry: {}", e);
+        let l2 = match &self.l2 {
+            Some(l2) => l2,
+            None => {
+                self.put_l1(url, &entry);
+                self.stats.record(url, CacheEvent::Store);
                 return Ok(());
             }
         };
 
-        // Store in Redis with TTL if available
-        if let Some(expires_at) = entry.expires_at {
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-
-            if expires_at > now {
-                let ttl = expires_at - now;
-                let _: () = con.set_ex(host, serialized_entry, ttl)?;
-            } else {
-                // Don't cache if already expired
-            }
-        } else {
-            // No expiration, use default
-            let _: () = con.set(host, serialized_entry)?;
+        // Keep it around for the stale window
+        match l2.put(url, &entry, entry.retain_until(self.config.stale_grace_period)) {
+            Ok(()) => {},
+            // Keep caching in L1 while L2 is down
+            Err(e) if e.is_unavailable() => {
+                self.put_l1(url, &entry);
+                self.stats.record(url, CacheEvent::Store);
+                return Ok(());
+            },
+            Err(e) => return Err(e),
         }
+        self.stats.record(url, CacheEvent::Store);
 
         // Increment hit counter to track access frequency
-        self.increment_hit_counter(host);
-
-        // Check if this entry qualifies for promotion to L1
-        // {
-        //     let hit_counters = self.hit_counters.read().unwrap();
-        //     if let Some(&hit_count) = hit_counters.get(host) {
-        //         if hit_count >= self.config.promotion_threshold {
-        //             // Move entry from L2 to L1
-        //             println!("Promoting {} to L1 cache", host);
-        //             self.put_l1(host, entry);
-        //         }
-        //     }
-        // }
+        self.increment_hit_counter(url);
+
+        // Replace an outdated copy already promoted to L1
+        if self.l1.contains(url).unwrap_or(false) {
+            self.put_l1(url, &entry);
+        }
+
         Ok(())
     }
 
-    /// Store a response in L1 (in-memory) cache without LRU eviction
-    fn put_l1(&self, host: &str, entry: CacheEntry) {
-        let mut l1_entries = match self.l1_entries.write() {
-            Ok(guard) => guard,
-            Err(poisoned) => poisoned.into_inner(),
-        };
+    /// Counts a response that isn't cached because it is larger than the maximum object size
+    pub fn reject_oversized(&self, url: &str) {
+        println!("Not caching {}: larger than the maximum object size of {} bytes", url, self.config.max_object_size);
+        self.stats.record(url, CacheEvent::Reject);
+    }
 
-        let mut lru_queue = match self.lru_queue.lock() {
-            Ok(guard) => guard,
-            Err(poisoned) => poisoned.into_inner(),
-        };
+    /// Store a response in L1, to be removed after its TTL and stale window
+    fn put_l1(&self, url: &str, entry: &CacheEntry) {
+        if let Err(e) = self.l1.put(url, entry, entry.retain_until(self.config.stale_grace_period)) {
+            println!("Error storing {} in L1 cache: {}", url, e);
+        }
+    }
 
-        // Check if we need to evict an entry (works)
-        if l1_entries.len() >= self.config.l1_max_size {
-            // Evict the least recently used entry
-            if let Some(oldest_host) = lru_queue.pop_front() {
-                l1_entries.remove(&oldest_host);
-            }
+    /// Counts response bytes sent to a client from the cache
+    pub fn record_served(&self, url: &str, bytes: usize) {
+        self.stats.record_served(url, bytes);
+    }
+
+    /// Hit, miss and store counters, in total and per host
+    pub fn stats(&self) -> CacheStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Compression results per content type
+    pub fn compression_stats(&self) -> Vec<(String, CompressionTotals)> {
+        self.compressor.totals()
+    }
+
+    /// Backend and statistics of each cache tier
+    pub fn tier_stats(&self) -> Vec<TierStats> {
+        let mut tiers = vec![TierStats {
+            tier: "L1",
+            backend: self.l1.name(),
+            health: self.l1.health(),
+            stats: self.l1.stats(),
+        }];
+        if let Some(l2) = &self.l2 {
+            tiers.push(TierStats {
+                tier: "L2",
+                backend: l2.name(),
+                health: l2.health(),
+                stats: l2.stats(),
+            });
         }
+        tiers
+    }
 
-        // Add to L1 cache
-        l1_entries.insert(host.to_string(), (entry.clone(), 1));
-        lru_queue.push_back(host.to_string());
+    /// Metadata of every L1 entry, sorted by URL
+    ///
+    /// Inspection doesn't count as a hit
+    pub fn l1_entries(&self) -> Result<Vec<StoredEntry>, CacheError> {
+        let mut entries = self.l1.entries()?;
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(entries)
+    }
 
-        // Schedule removal from L1 cache after TTL (Doesn't really work)
-        if let Some(expires_at) = entry.expires_at {
-            let host_clone = host.to_string();
-            let l1_entries_clone = self.l1_entries.clone();
+    /// Checks if either tier holds a URL, without counting it as a hit
+    pub fn contains(&self, url: &str) -> bool {
+        self.l1.contains(url).unwrap_or(false)
+            || self.l2.as_ref().map(|l2| l2.contains(url).unwrap_or(false)).unwrap_or(false)
+    }
 
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
+    /// The entry for a URL in each tier, without counting it as a hit
+    pub fn locate(&self, url: &str) -> Vec<TierEntry> {
+        let tiers = std::iter::once(("L1", &self.l1)).chain(self.l2.iter().map(|l2| ("L2", l2)));
+        tiers
+            .map(|(tier, store)| TierEntry {
+                tier,
+                backend: store.name(),
+                entry: store.peek(url),
+            })
+            .collect()
+    }
 
-            if expires_at > now {
-                let ttl = expires_at - now;
+    /// L2 keys matching a glob pattern (`*` and `?`), sorted
+    ///
+    /// - returns None without an L2
+    pub fn l2_keys(&self, pattern: &str) -> Option<Result<Vec<String>, CacheError>> {
+        let l2 = self.l2.as_ref()?;
+        Some(l2.scan().map(|keys| {
+            let mut keys: Vec<String> = keys.into_iter().filter(|key| glob_match(pattern, key)).collect();
+            keys.sort();
+            keys
+        }))
+    }
 
-                thread::spawn(move || {
-                    thread::sleep(Duration::from_secs(ttl));
+    /// Writes every entry of both tiers, with its metadata, to a snapshot file
+    pub fn export(&self, path: &Path) -> Result<SnapshotReport, CacheError> {
+        let mut tiers: Vec<(SnapshotTier, &dyn CacheStore)> = vec![(SnapshotTier::L1, self.l1.as_ref())];
+        if let Some(l2) = &self.l2 {
+            tiers.push((SnapshotTier::L2, l2.as_ref()));
+        }
+        write_snapshot(path, &tiers)
+    }
 
-                    let mut l1_entries = match l1_entries_clone.write() {
-                        Ok(guard) => guard,
-                        Err(poisoned) => poisoned.into_inner(),
-                    };
+    /// Loads a snapshot file, each entry into the tier it was exported from
+    ///
+    /// L2 entries go to L1 when there is no L2 or it is unavailable, entries already cached are replaced
+    pub fn import(&self, path: &Path) -> Result<SnapshotReport, CacheError> {
+        read_snapshot(path, |tier, url, entry, retain_until| {
+            // Same limit as for responses, the snapshot may come from a roxy with a larger one;
+            // compressed entries are checked by their original size, which decompress caps
+            let size = match entry.compression {
+                BodyCompression::None => Some(entry.response_data.len()),
+                _ => self.compressor.decompress(entry.clone()).map(|original| original.response_data.len()),
+            };
+            match size {
+                Some(size) if size <= self.config.max_object_size => {},
+                Some(size) => {
+                    println!("Not importing {}: {} bytes exceeds the maximum object size", url, size);
+                    self.stats.record(url, CacheEvent::Reject);
+                    return false;
+                },
+                None => {
+                    println!("Not importing {}: unreadable or larger than the maximum object size once decompressed", url);
+                    self.stats.record(url, CacheEvent::Reject);
+                    return false;
+                }
+            }
 
-                    l1_entries.remove(&host_clone);
-                });
+            let result = match (tier, &self.l2) {
+                (SnapshotTier::L2, Some(l2)) => match l2.put(url, &entry, retain_until) {
+                    Err(e) if e.is_unavailable() => self.l1.put(url, &entry, retain_until),
+                    result => result,
+                },
+                _ => self.l1.put(url, &entry, retain_until),
+            };
+            match result {
+                Ok(()) => {
+                    self.stats.record(url, CacheEvent::Store);
+                    true
+                },
+                Err(e) => {
+                    println!("Failed to import {}: {}", url, e);
+                    false
+                }
             }
+        })
+    }
+
+    /// Stops or resumes expiry in the stores, so offline mode keeps serving expired entries
+    pub fn set_expiry_paused(&self, paused: bool) {
+        self.l1.set_expiry_paused(paused);
+        if let Some(l2) = &self.l2 {
+            l2.set_expiry_paused(paused);
         }
     }
 
-    /// Clear the entire cache (both L1 and L2)
-    pub fn clear(&self) -> Result<(), RedisError> {
-        // Clear L1
+    /// Stops the promotion worker and the stores' background threads
+    pub fn shutdown(&self) {
+        self.promotions.shutdown();
+        self.l1.shutdown();
+        if let Some(l2) = &self.l2 {
+            l2.shutdown();
+        }
+    }
+
+    /// Removes the entry for a URL from both tiers
+    ///
+    /// - returns true if any tier held it
+    pub fn invalidate(&self, url: &str) -> Result<bool, CacheError> {
         {
-            let mut l1_entries = match self.l1_entries.write() {
+            let mut hit_counters = match self.hit_counters.write() {
                 Ok(guard) => guard,
                 Err(poisoned) => poisoned.into_inner(),
             };
-            l1_entries.clear();
+            hit_counters.remove(url);
+        }
 
-            let mut lru_queue = match self.lru_queue.lock() {
-                Ok(guard) => guard,
-                Err(poisoned) => poisoned.into_inner(),
-            };
-            lru_queue.clear();
+        let mut removed = self.l1.delete(url)?;
+        if let Some(l2) = &self.l2 {
+            removed |= l2.delete(url)?;
+        }
+        Ok(removed)
+    }
+
+    /// Removes every entry whose URL matches the rule from both tiers
+    ///
+    /// - returns the number of URLs removed
+    pub fn ban(&self, rule: &BanRule) -> Result<usize, CacheError> {
+        let tiers = std::iter::once(&self.l1).chain(self.l2.iter());
+        let mut banned = HashSet::new();
+        for store in tiers {
+            for url in store.scan()? {
+                if rule.matches(&url) && store.delete(&url)? {
+                    banned.insert(url);
+                }
+            }
         }
 
-        // Clear L2 (Redis)
-        let mut con = self.get_connection()?;
-        let _: () = redis::cmd("FLUSHDB").query(&mut con)?;
+        let mut hit_counters = match self.hit_counters.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        hit_counters.retain(|url, _| !rule.matches(url));
+        Ok(banned.len())
+    }
 
+    /// Clear the entire cache (both L1 and L2)
+    pub fn clear(&self) -> Result<(), CacheError> {
+        self.l1.clear()?;
+        if let Some(l2) = &self.l2 {
+            l2.clear()?;
+        }
         Ok(())
     }
 }
@@ -454,16 +976,23 @@ impl HttpCache {
 impl Clone for HttpCache {
     fn clone(&self) -> Self {
         HttpCache {
-            l1_entries: self.l1_entries.clone(),
+            l1: self.l1.clone(),
+            l2: self.l2.clone(),
             hit_counters: self.hit_counters.clone(),
-            db_client: self.db_client.clone(), // Clones only the client handle, not the connection
-            config: CacheConfig {
-                l1_max_size: self.config.l1_max_size,
-                l1_default_ttl: self.config.l1_default_ttl,
-                promotion_threshold: self.config.promotion_threshold,
-                redis_url: self.config.redis_url.clone(),
-            },
-            lru_queue: self.lru_queue.clone(),
+            config: self.config.clone(),
+            revalidating: self.revalidating.clone(),
+            in_flight: self.in_flight.clone(),
+            promotions: self.promotions.clone(),
+            compressor: self.compressor.clone(),
+            stats: self.stats.clone(),
         }
     }
 }
+
+/// Current UNIX time in seconds
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
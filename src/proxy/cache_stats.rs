@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::utils::parsing::url_authority;
+
+// Hit/miss accounting of HttpCache, kept in total and per host (authority of the cached URL)
+
+/// Something that happened to a cached URL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheEvent {
+    L1Hit,
+    L2Hit,
+    Miss,
+    /// Response written to the cache
+    Store,
+    /// Response not cached (no-store, already expired or too large)
+    Reject,
+    /// Entry dropped by a store to make room
+    Eviction,
+    /// Entry copied from L2 to L1
+    Promotion,
+    /// Background refresh of a stale entry started
+    Revalidation,
+}
+
+/// Cache counters, of all hosts or of one
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheCounters {
+    pub l1_hits: u64,
+    pub l2_hits: u64,
+    pub misses: u64,
+    pub stores: u64,
+    pub rejects: u64,
+    pub evictions: u64,
+    pub promotions: u64,
+    pub revalidations: u64,
+
+    /// Response bytes sent to clients from the cache
+    pub bytes_served: u64,
+}
+
+impl CacheCounters {
+    fn record(&mut self, event: CacheEvent) {
+        match event {
+            CacheEvent::L1Hit => self.l1_hits += 1,
+            CacheEvent::L2Hit => self.l2_hits += 1,
+            CacheEvent::Miss => self.misses += 1,
+            CacheEvent::Store => self.stores += 1,
+            CacheEvent::Reject => self.rejects += 1,
+            CacheEvent::Eviction => self.evictions += 1,
+            CacheEvent::Promotion => self.promotions += 1,
+            CacheEvent::Revalidation => self.revalidations += 1,
+        }
+    }
+
+    /// Lookups answered by the cache or the origin
+    pub fn lookups(&self) -> u64 {
+        self.l1_hits + self.l2_hits + self.misses
+    }
+
+    /// Share of lookups answered from either tier (0.0 without lookups)
+    pub fn hit_ratio(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => (self.l1_hits + self.l2_hits) as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Copy of the counters at one point in time
+#[derive(Clone, Debug, Default)]
+pub struct CacheStatsSnapshot {
+    pub totals: CacheCounters,
+
+    /// Counters per host, most lookups first
+    pub hosts: Vec<(String, CacheCounters)>,
+}
+
+#[derive(Default)]
+struct Counters {
+    totals: CacheCounters,
+    hosts: HashMap<String, CacheCounters>,
+}
+
+///
+/// Thread-safe cache counters shared by all clones of HttpCache
+///
+#[derive(Default)]
+pub struct CacheStats {
+    counters: Mutex<Counters>,
+}
+
+impl CacheStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Counters> {
+        match self.counters.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Counts an event for the host of a cached URL
+    pub fn record(&self, url: &str, event: CacheEvent) {
+        let mut counters = self.lock();
+        counters.totals.record(event);
+        counters.hosts.entry(url_authority(url).to_string()).or_default().record(event);
+    }
+
+    /// Adds response bytes sent from the cache
+    pub fn record_served(&self, url: &str, bytes: usize) {
+        let mut counters = self.lock();
+        counters.totals.bytes_served += bytes as u64;
+        counters.hosts.entry(url_authority(url).to_string()).or_default().bytes_served += bytes as u64;
+    }
+
+    pub fn snapshot(&self) -> CacheStatsSnapshot {
+        let counters = self.lock();
+        let mut hosts: Vec<_> = counters.hosts.iter().map(|(host, counters)| (host.clone(), *counters)).collect();
+        hosts.sort_by(|(a_host, a), (b_host, b)| b.lookups().cmp(&a.lookups()).then_with(|| a_host.cmp(b_host)));
+        CacheStatsSnapshot {
+            totals: counters.totals,
+            hosts,
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use crate::proxy::cache::CacheEntry;
+use crate::proxy::compression::BodyCompression;
+use crate::proxy::store::CacheError;
+
+// Binary encoding of cache entries for stores that keep the body separately:
+// the metadata (everything but the body) is encoded by `encode_meta`, the body is stored as raw bytes
+//
+// Metadata layout, integers little endian:
+// u8 format version
+// u64 timestamp
+// opt<u64> expires_at, opt<u64> stale_if_error, opt<u64> stale_while_revalidate
+// u8 must_revalidate
+// u8 body compression, 0 none, 1 gzip (since version 2, version 1 bodies are uncompressed)
+// opt<str> etag, opt<str> last_modified
+// u32 header count, then (str name, str value) per header
+// where opt<T> is a u8 flag followed by T if set, and str is a u32 length followed by UTF-8 bytes
+
+/// Current version of the metadata layout
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Encodes everything but the body of an entry
+pub fn encode_meta(entry: &CacheEntry) -> Vec<u8> {
+    let mut out = Vec::with_capacity(64 + entry.headers.len() * 32);
+    out.push(FORMAT_VERSION);
+    out.extend_from_slice(&entry.timestamp.to_le_bytes());
+    put_opt_u64(&mut out, entry.expires_at);
+    put_opt_u64(&mut out, entry.stale_if_error);
+    put_opt_u64(&mut out, entry.stale_while_revalidate);
+    out.push(entry.must_revalidate as u8);
+    out.push(match entry.compression {
+        BodyCompression::None => 0,
+        BodyCompression::Gzip => 1,
+    });
+    put_opt_str(&mut out, entry.etag.as_deref());
+    put_opt_str(&mut out, entry.last_modified.as_deref());
+
+    out.extend_from_slice(&(entry.headers.len() as u32).to_le_bytes());
+    for (name, value) in &entry.headers {
+        put_str(&mut out, name);
+        put_str(&mut out, value);
+    }
+    out
+}
+
+/// Rebuilds an entry from its encoded metadata and body
+pub fn decode_entry(meta: &[u8], response_data: Vec<u8>) -> Result<CacheEntry, CacheError> {
+    let mut reader = Reader { data: meta, pos: 0 };
+
+    let version = reader.u8()?;
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(CacheError::Serialization(format!("Unsupported entry format version {}", version)));
+    }
+
+    let timestamp = reader.u64()?;
+    let expires_at = reader.opt_u64()?;
+    let stale_if_error = reader.opt_u64()?;
+    let stale_while_revalidate = reader.opt_u64()?;
+    let must_revalidate = reader.u8()? != 0;
+    let compression = match version {
+        1 => BodyCompression::None,
+        _ => match reader.u8()? {
+            0 => BodyCompression::None,
+            1 => BodyCompression::Gzip,
+            other => return Err(CacheError::Serialization(format!("Unknown body compression {}", other))),
+        },
+    };
+    let etag = reader.opt_str()?;
+    let last_modified = reader.opt_str()?;
+
+    let header_count = reader.u32()? as usize;
+    let mut headers = HashMap::with_capacity(header_count.min(256));
+    for _ in 0..header_count {
+        let name = reader.str()?;
+        let value = reader.str()?;
+        headers.insert(name, value);
+    }
+
+    Ok(CacheEntry {
+        response_data,
+        headers,
+        timestamp,
+        last_modified,
+        etag,
+        expires_at,
+        stale_if_error,
+        must_revalidate,
+        stale_while_revalidate,
+        compression,
+    })
+}
+
+fn put_opt_u64(out: &mut Vec<u8>, value: Option<u64>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            out.extend_from_slice(&value.to_le_bytes());
+        },
+        None => out.push(0),
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn put_opt_str(out: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            put_str(out, value);
+        },
+        None => out.push(0),
+    }
+}
+
+/// Cursor over encoded metadata, every read fails cleanly on truncated input
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], CacheError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+        match end {
+            Some(end) => {
+                let bytes = &self.data[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            },
+            None => Err(CacheError::Serialization("Truncated entry metadata".to_string())),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, CacheError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn opt_u64(&mut self) -> Result<Option<u64>, CacheError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.u64()?)),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, CacheError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| CacheError::Serialization("Invalid UTF-8 in entry metadata".to_string()))
+    }
+
+    fn opt_str(&mut self) -> Result<Option<String>, CacheError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.str()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> CacheEntry {
+        let mut headers = HashMap::new();
+        headers.insert("content-type".to_string(), "text/html".to_string());
+        headers.insert("etag".to_string(), "\"abc\"".to_string());
+        CacheEntry {
+            response_data: Vec::new(),
+            headers,
+            timestamp: 1_700_000_000,
+            last_modified: None,
+            etag: Some("\"abc\"".to_string()),
+            expires_at: Some(1_700_000_300),
+            stale_if_error: None,
+            must_revalidate: true,
+            stale_while_revalidate: Some(30),
+            compression: BodyCompression::Gzip,
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_every_field() {
+        let entry = sample();
+        let decoded = decode_entry(&encode_meta(&entry), b"body".to_vec()).unwrap();
+
+        assert_eq!(decoded.response_data, b"body");
+        assert_eq!(decoded.headers, entry.headers);
+        assert_eq!(decoded.timestamp, entry.timestamp);
+        assert_eq!(decoded.etag, entry.etag);
+        assert_eq!(decoded.last_modified, None);
+        assert_eq!(decoded.expires_at, entry.expires_at);
+        assert_eq!(decoded.stale_if_error, None);
+        assert!(decoded.must_revalidate);
+        assert_eq!(decoded.stale_while_revalidate, Some(30));
+        assert_eq!(decoded.compression, BodyCompression::Gzip);
+    }
+
+    #[test]
+    fn version_1_has_no_compression_byte() {
+        let mut meta = vec![1];
+        meta.extend_from_slice(&42u64.to_le_bytes());
+        meta.extend_from_slice(&[0, 0, 0]); // expires_at, stale_if_error, stale_while_revalidate
+        meta.push(0); // must_revalidate
+        meta.extend_from_slice(&[0, 0]); // etag, last_modified
+        meta.extend_from_slice(&0u32.to_le_bytes());
+
+        let decoded = decode_entry(&meta, Vec::new()).unwrap();
+        assert_eq!(decoded.timestamp, 42);
+        assert_eq!(decoded.compression, BodyCompression::None);
+    }
+
+    #[test]
+    fn legacy_json_entries_still_deserialize() {
+        // Written before the stale windows and compression existed
+        let json = r#"{"response_data":[104,105],"headers":{"etag":"x"},"timestamp":7,"last_modified":null,"etag":"x","expires_at":null}"#;
+        let entry: CacheEntry = serde_json::from_str(json).unwrap();
+
+        assert_eq!(entry.response_data, b"hi");
+        assert_eq!(entry.stale_while_revalidate, None);
+        assert!(!entry.must_revalidate);
+        assert_eq!(entry.compression, BodyCompression::None);
+    }
+
+    #[test]
+    fn rejects_truncated_and_unknown_versions() {
+        let meta = encode_meta(&sample());
+        for len in [0, 1, 9, meta.len() - 1] {
+            assert!(decode_entry(&meta[..len], Vec::new()).is_err(), "decoded {} bytes", len);
+        }
+
+        let mut future = meta.clone();
+        future[0] = FORMAT_VERSION + 1;
+        assert!(decode_entry(&future, Vec::new()).is_err());
+        future[0] = 0;
+        assert!(decode_entry(&future, Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::cache::CacheEntry;
+use crate::utils::parsing::get_header;
+
+// Optional compression of cached responses:
+// CompressionConfig - whether and what to compress (part of CacheConfig)
+// BodyCompression - how a stored entry's data is compressed
+// Compressor - compresses on put, decompresses on hit and keeps per content type statistics
+
+/// How the data of a stored entry is compressed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BodyCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+///
+/// Settings for compressing cached responses
+///
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    /// Compress stored responses at all
+    pub enabled: bool,
+
+    /// Responses smaller than this (in bytes) are stored as they are
+    pub min_size: usize,
+
+    /// gzip level, 1 (fastest) to 9 (smallest)
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: false,
+            min_size: 1024,
+            level: 6,
+        }
+    }
+}
+
+/// Compression results of one content type
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompressionTotals {
+    /// Entries stored compressed
+    pub compressed: u64,
+
+    /// Entries that were compressed but didn't get smaller, stored as they are
+    pub incompressible: u64,
+
+    /// Size of the compressed entries before and after compression
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+
+    pub compress_time: Duration,
+
+    /// Hits that had to be decompressed
+    pub decompressed: u64,
+    pub decompress_time: Duration,
+}
+
+impl CompressionTotals {
+    /// Compressed size relative to the original (1.0 means no gain)
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 1.0;
+        }
+        self.bytes_out as f64 / self.bytes_in as f64
+    }
+}
+
+///
+/// Compresses entries before they are stored and decompresses them on hits
+///
+pub struct Compressor {
+    config: CompressionConfig,
+
+    /// Largest data a decompressed entry may have (the cache's max_object_size)
+    max_size: usize,
+
+    totals: Mutex<HashMap<String, CompressionTotals>>,
+}
+
+impl Compressor {
+    pub fn new(config: CompressionConfig, max_size: usize) -> Self {
+        Compressor {
+            config,
+            max_size,
+            totals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, CompressionTotals>> {
+        match self.totals.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Compresses the entry's data if enabled, large enough and not compressed by the origin already
+    pub fn compress(&self, mut entry: CacheEntry) -> CacheEntry {
+        if !self.config.enabled
+            || entry.compression != BodyCompression::None
+            || entry.response_data.len() < self.config.min_size
+            || is_precompressed(&entry.headers)
+        {
+            return entry;
+        }
+
+        let started = Instant::now();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(self.config.level.clamp(1, 9)));
+        let compressed = match encoder.write_all(&entry.response_data).and_then(|_| encoder.finish()) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                println!("Failed to compress cache entry: {}", e);
+                return entry;
+            }
+        };
+        let elapsed = started.elapsed();
+
+        let mut totals = self.lock();
+        let totals = totals.entry(content_type(&entry.headers)).or_default();
+        totals.compress_time += elapsed;
+        if compressed.len() >= entry.response_data.len() {
+            totals.incompressible += 1;
+            return entry;
+        }
+        totals.compressed += 1;
+        totals.bytes_in += entry.response_data.len() as u64;
+        totals.bytes_out += compressed.len() as u64;
+
+        entry.response_data = compressed;
+        entry.compression = BodyCompression::Gzip;
+        entry
+    }
+
+    /// Restores the original data of a compressed entry
+    ///
+    /// - returns None if the stored data is corrupt or inflates past the maximum size
+    pub fn decompress(&self, mut entry: CacheEntry) -> Option<CacheEntry> {
+        if entry.compression == BodyCompression::None {
+            return Some(entry);
+        }
+
+        let started = Instant::now();
+        let mut data = Vec::with_capacity((entry.response_data.len() * 3).min(self.max_size));
+        // One byte over the limit tells a too large entry from one of exactly the maximum size
+        let limit = self.max_size as u64 + 1;
+        if let Err(e) = GzDecoder::new(&entry.response_data[..]).take(limit).read_to_end(&mut data) {
+            println!("Failed to decompress cache entry: {}", e);
+            return None;
+        }
+        if data.len() > self.max_size {
+            println!("Failed to decompress cache entry: more than {} bytes", self.max_size);
+            return None;
+        }
+
+        let mut totals = self.lock();
+        let totals = totals.entry(content_type(&entry.headers)).or_default();
+        totals.decompressed += 1;
+        totals.decompress_time += started.elapsed();
+
+        entry.response_data = data;
+        entry.compression = BodyCompression::None;
+        Some(entry)
+    }
+
+    /// Results per content type, largest savings first
+    pub fn totals(&self) -> Vec<(String, CompressionTotals)> {
+        let mut totals: Vec<_> = self.lock().iter().map(|(content_type, totals)| (content_type.clone(), *totals)).collect();
+        totals.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.bytes_in.saturating_sub(totals.bytes_out)));
+        totals
+    }
+}
+
+/// Media type of a response without parameters, "unknown" if not set
+fn content_type(headers: &HashMap<String, String>) -> String {
+    get_header(headers, "content-type")
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| media_type.trim().to_lowercase())
+        .filter(|media_type| !media_type.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Checks if compressing the response again would be wasted work
+fn is_precompressed(headers: &HashMap<String, String>) -> bool {
+    if let Some(encoding) = get_header(headers, "content-encoding") {
+        if !encoding.trim().eq_ignore_ascii_case("identity") {
+            return true;
+        }
+    }
+
+    let media_type = content_type(headers);
+    media_type.starts_with("image/") && media_type != "image/svg+xml"
+        || media_type.starts_with("video/")
+        || media_type.starts_with("audio/")
+        || matches!(
+            media_type.as_str(),
+            "application/zip" | "application/gzip" | "application/x-gzip" | "application/zstd"
+                | "application/x-7z-compressed" | "application/x-bzip2" | "application/x-xz"
+                | "font/woff" | "font/woff2"
+        )
+}
@@ -0,0 +1,222 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::proxy::cache::CacheConfig;
+use crate::proxy::store::StoreBackend;
+use crate::proxy::upstream::{RetryPolicy, UpstreamTimeouts};
+use crate::utils::block_page::BlockPage;
+use crate::utils::responses::{ErrorTemplates, Status};
+
+///
+/// Configuration for the proxy, read from the command line
+///
+pub struct ProxyConfig {
+    /// Port the proxy listens on
+    pub port: u16,
+
+    /// Page served for blocked plain HTTP requests
+    pub block_page: BlockPage,
+
+    /// HTML templates for errors generated by roxy (plain text when empty)
+    pub error_templates: ErrorTemplates,
+
+    /// Timeouts for upstream servers
+    pub timeouts: UpstreamTimeouts,
+
+    /// Retry budget for idempotent requests
+    pub retry: RetryPolicy,
+
+    /// Configuration of HttpCache
+    pub cache: CacheConfig,
+
+    /// Clients allowed to send PURGE and BAN requests
+    pub purge_allowed: Vec<IpAddr>,
+
+    /// URL list or access log to warm up the cache with on startup
+    pub warm_from: Option<PathBuf>,
+
+    /// Requests a warm-up makes at a time
+    pub warm_concurrency: usize,
+
+    /// Cache snapshot loaded on startup
+    pub import_from: Option<PathBuf>,
+
+    /// Serve HTTP requests from the cache only, toggled from the console
+    offline: AtomicBool,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            port: 6505,
+            block_page: BlockPage::default(),
+            error_templates: ErrorTemplates::default(),
+            timeouts: UpstreamTimeouts::default(),
+            retry: RetryPolicy::default(),
+            cache: CacheConfig::default(),
+            purge_allowed: vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)],
+            warm_from: None,
+            warm_concurrency: 4,
+            import_from: None,
+            offline: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Builds the configuration from command line arguments
+    ///
+    /// Supported arguments:
+    /// * `--port <port>`
+    /// * `--block-page <file>` - HTML block page template
+    /// * `--error-page [<status>=]<file>` - HTML error template, for one status or all of them
+    /// * `--dns-timeout`, `--connect-timeout`, `--first-byte-timeout`, `--total-timeout <seconds>`
+    /// * `--retries <count>`, `--retry-backoff <seconds>`
+    /// * `--l1 <backend>`, `--l2 <backend|none>` - cache stores (memory, redis or disk)
+    /// * `--cache-dir <dir>` - directory of the disk store
+    /// * `--cache-dir-size <MiB>` - size cap of the disk store
+    /// * `--redis <url>` - Redis server (`redis://` or `rediss://`, credentials and database in the URL)
+    /// * `--redis-pool-size <count>` - maximum number of Redis connections
+    /// * `--redis-connect-timeout <secs>` - time for connecting to Redis (TCP, TLS and AUTH)
+    /// * `--redis-command-timeout <secs>` - read/write timeout of a Redis command
+    /// * `--redis-checkout-timeout <secs>` - wait for a free pooled Redis connection
+    /// * `--redis-prefix <prefix>` - namespace of roxy's Redis keys
+    /// * `--compress` - gzip cached responses that aren't compressed already
+    /// * `--compress-min-size <bytes>` - smallest response worth compressing
+    /// * `--compress-level <1-9>` - gzip level
+    /// * `--purge-allow <ip>` - client allowed to send PURGE and BAN requests, besides localhost
+    /// * `--warm <file>` - URL list or access log to warm up the cache with on startup
+    /// * `--warm-concurrency <count>` - requests a warm-up makes at a time
+    /// * `--import <file>` - cache snapshot (see the console's export command) loaded on startup
+    /// * `--offline` - never contact origins, answer HTTP requests from the cache only
+    ///   (expiry is paused in the memory and disk stores, Redis still drops entries after the stale grace period)
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<ProxyConfig, String> {
+        let mut config = ProxyConfig::default();
+        let mut args = args.skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--port" => {
+                    let value = args.next().ok_or("--port requires a value")?;
+                    config.port = value.parse().map_err(|_| format!("Invalid port: {}", value))?;
+                },
+                "--block-page" => {
+                    let path = args.next().ok_or("--block-page requires a file")?;
+                    config.block_page = BlockPage::from_file(&path)
+                        .map_err(|e| format!("Failed to read block page {}: {}", path, e))?;
+                },
+                "--error-page" => {
+                    let value = args.next().ok_or("--error-page requires a file")?;
+                    let (status, path) = match value.split_once('=') {
+                        Some((code, path)) => {
+                            let status = code
+                                .parse()
+                                .ok()
+                                .and_then(Status::from_code)
+                                .ok_or(format!("Unsupported status for --error-page: {}", code))?;
+                            (Some(status), path)
+                        },
+                        None => (None, value.as_str()),
+                    };
+                    config.error_templates
+                        .load(status, path)
+                        .map_err(|e| format!("Failed to read error page {}: {}", path, e))?;
+                },
+                "--dns-timeout" => config.timeouts.dns = parse_seconds(&arg, args.next())?,
+                "--connect-timeout" => config.timeouts.connect = parse_seconds(&arg, args.next())?,
+                "--first-byte-timeout" => config.timeouts.first_byte = parse_seconds(&arg, args.next())?,
+                "--total-timeout" => config.timeouts.total = parse_seconds(&arg, args.next())?,
+                "--retries" => {
+                    let value = args.next().ok_or("--retries requires a count")?;
+                    config.retry.max_retries = value.parse().map_err(|_| format!("Invalid retry count: {}", value))?;
+                },
+                "--retry-backoff" => config.retry.base_backoff = parse_seconds(&arg, args.next())?,
+                "--l1" => config.cache.l1_backend = parse_backend(&arg, args.next())?,
+                "--l2" => {
+                    let value = args.next().ok_or("--l2 requires a backend")?;
+                    config.cache.l2_backend = match value.as_str() {
+                        "none" => None,
+                        _ => Some(parse_backend(&arg, Some(value))?),
+                    };
+                },
+                "--cache-dir" => {
+                    let value = args.next().ok_or("--cache-dir requires a directory")?;
+                    config.cache.disk_path = PathBuf::from(value);
+                },
+                "--redis" => config.cache.redis.url = args.next().ok_or("--redis requires a URL")?,
+                "--redis-prefix" => config.cache.redis.key_prefix = args.next().ok_or("--redis-prefix requires a prefix")?,
+                "--redis-pool-size" => {
+                    let value = args.next().ok_or("--redis-pool-size requires a count")?;
+                    config.cache.redis.pool_size = match value.parse() {
+                        Ok(size) if size > 0 => size,
+                        _ => return Err(format!("Invalid Redis pool size: {}", value)),
+                    };
+                },
+                "--redis-connect-timeout" => config.cache.redis.connect_timeout = parse_seconds(&arg, args.next())?,
+                "--redis-command-timeout" => config.cache.redis.command_timeout = parse_seconds(&arg, args.next())?,
+                "--redis-checkout-timeout" => config.cache.redis.checkout_timeout = parse_seconds(&arg, args.next())?,
+                "--cache-dir-size" => {
+                    let value = args.next().ok_or("--cache-dir-size requires a size in MiB")?;
+                    let mib: usize = value.parse().map_err(|_| format!("Invalid disk cache size: {}", value))?;
+                    config.cache.disk_max_bytes = mib * 1024 * 1024;
+                },
+                "--compress" => config.cache.compression.enabled = true,
+                "--compress-min-size" => {
+                    let value = args.next().ok_or("--compress-min-size requires a size in bytes")?;
+                    config.cache.compression.min_size = value.parse().map_err(|_| format!("Invalid compression size: {}", value))?;
+                },
+                "--compress-level" => {
+                    let value = args.next().ok_or("--compress-level requires a level")?;
+                    config.cache.compression.level = match value.parse() {
+                        Ok(level @ 1..=9) => level,
+                        _ => return Err(format!("Invalid compression level: {}", value)),
+                    };
+                },
+                "--purge-allow" => {
+                    let value = args.next().ok_or("--purge-allow requires an IP address")?;
+                    let ip = value.parse().map_err(|_| format!("Invalid IP address for --purge-allow: {}", value))?;
+                    config.purge_allowed.push(ip);
+                },
+                "--offline" => config.set_offline(true),
+                "--import" => config.import_from = Some(PathBuf::from(args.next().ok_or("--import requires a file")?)),
+                "--warm" => config.warm_from = Some(PathBuf::from(args.next().ok_or("--warm requires a file")?)),
+                "--warm-concurrency" => {
+                    let value = args.next().ok_or("--warm-concurrency requires a count")?;
+                    config.warm_concurrency = match value.parse() {
+                        Ok(count) if count > 0 => count,
+                        _ => return Err(format!("Invalid warm-up concurrency: {}", value)),
+                    };
+                },
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Checks if HTTP requests are answered from the cache only
+    pub fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::Relaxed)
+    }
+
+    pub fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::Relaxed);
+    }
+}
+
+/// Parses a positive number of seconds (fractions allowed) for a timeout option
+fn parse_seconds(option: &str, value: Option<String>) -> Result<Duration, String> {
+    let value = value.ok_or(format!("{} requires a number of seconds", option))?;
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("Invalid value for {}: {}", option, value)),
+    }
+}
+
+/// Parses a cache store backend name (memory, redis or disk)
+fn parse_backend(option: &str, value: Option<String>) -> Result<StoreBackend, String> {
+    let value = value.ok_or(format!("{} requires a backend", option))?;
+    StoreBackend::from_name(&value).ok_or(format!("Unknown cache backend for {}: {}", option, value))
+}
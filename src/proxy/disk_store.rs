@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::proxy::cache::{now_secs, CacheEntry};
+use crate::proxy::eviction::{EvictionPolicy, EvictionTracker};
+use crate::proxy::l1::ENTRY_OVERHEAD;
+use crate::proxy::store::{CacheError, CacheStore, EvictionListener, StoreStats, StoredEntry};
+
+// Layout of the disk store directory:
+// index.json - metadata of every entry (headers, expiry, body hash, last access)
+// bodies/<sha256> - response bodies, shared by entries with the same content
+// Every file is written to a temporary name first and renamed into place,
+// so a crash leaves either the old or the new version; orphans are removed on startup
+// The index is kept in memory and written by a flusher thread once changed (and on shutdown),
+// a crash loses at most the last FLUSH_INTERVAL of changes; the flusher also drops expired records
+// (not while expiry is paused for offline mode, which is why reload keeps them for it)
+
+const INDEX_FILE: &str = "index.json";
+const BODIES_DIR: &str = "bodies";
+const INDEX_VERSION: u32 = 1;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Metadata of one entry as kept in the index
+#[derive(Serialize, Deserialize, Clone)]
+struct IndexRecord {
+    key: String,
+
+    /// SHA-256 of the body, also the name of its file
+    body: String,
+
+    /// Body length, checked when reading it back
+    size: usize,
+
+    retain_until: Option<u64>,
+
+    /// Last get or put (UNIX seconds), restores the LRU order on startup
+    last_access: u64,
+
+    /// The entry without its body
+    entry: CacheEntry,
+}
+
+impl IndexRecord {
+    fn is_expired(&self, now: u64) -> bool {
+        self.retain_until.map(|retain_until| retain_until <= now).unwrap_or(false)
+    }
+
+    fn stored_entry(&self) -> StoredEntry {
+        StoredEntry {
+            key: self.key.clone(),
+            entry: self.entry.clone(),
+            size: self.size,
+            hits: None,
+            retain_until: self.retain_until,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    version: u32,
+    entries: Vec<IndexRecord>,
+}
+
+struct DiskState {
+    index: HashMap<String, IndexRecord>,
+
+    /// Number of index records per body hash
+    body_refs: HashMap<String, usize>,
+
+    /// LRU order and size of the entries, keeps the store within its cap
+    tracker: EvictionTracker,
+
+    evictions: u64,
+    expirations: u64,
+    listener: Option<EvictionListener>,
+
+    /// Index changed since it was last written
+    dirty: bool,
+}
+
+/// Part of the store shared with the flusher thread
+struct DiskShared {
+    dir: PathBuf,
+    state: Mutex<DiskState>,
+
+    /// Held while writing the index, so an older copy can't replace a newer one
+    flushing: Mutex<()>,
+
+    /// Set on shutdown, the flusher waits on `wakeup` for it
+    stopping: Mutex<bool>,
+    wakeup: Condvar,
+
+    /// Expired records are kept and served while set
+    expiry_paused: AtomicBool,
+}
+
+///
+/// Persistent store with content-addressed body files and an index of metadata,
+/// bounded in bytes by LRU eviction
+///
+pub struct DiskStore {
+    shared: Arc<DiskShared>,
+    flusher: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Distinguishes temporary files of concurrent writes
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl DiskStore {
+    /// Opens the store directory (created if needed), reloads its index and starts the flusher thread
+    pub fn open(dir: &Path, max_bytes: usize) -> Result<Self, CacheError> {
+        fs::create_dir_all(dir.join(BODIES_DIR))?;
+
+        let shared = Arc::new(DiskShared {
+            dir: dir.to_path_buf(),
+            state: Mutex::new(DiskState {
+                index: HashMap::new(),
+                body_refs: HashMap::new(),
+                tracker: EvictionTracker::new(EvictionPolicy::Lru, max_bytes),
+                evictions: 0,
+                expirations: 0,
+                listener: None,
+                dirty: false,
+            }),
+            flushing: Mutex::new(()),
+            stopping: Mutex::new(false),
+            wakeup: Condvar::new(),
+            expiry_paused: AtomicBool::new(false),
+        });
+        shared.reload()?;
+        shared.flush_index()?;
+
+        let thread_shared = Arc::clone(&shared);
+        let handle = thread::spawn(move || flush_periodically(thread_shared));
+
+        Ok(DiskStore {
+            shared,
+            flusher: Mutex::new(Some(handle)),
+        })
+    }
+}
+
+/// Flusher loop: drops expired records and writes the index every FLUSH_INTERVAL if it changed, until shutdown
+fn flush_periodically(shared: Arc<DiskShared>) {
+    let mut stopping = lock(&shared.stopping);
+    while !*stopping {
+        stopping = match shared.wakeup.wait_timeout(stopping, FLUSH_INTERVAL) {
+            Ok((guard, _)) => guard,
+            Err(poisoned) => poisoned.into_inner().0,
+        };
+        if *stopping {
+            break;
+        }
+
+        drop(stopping);
+        shared.drop_expired();
+        if let Err(e) = shared.flush_index() {
+            println!("Failed to save disk cache index: {}", e);
+        }
+        stopping = lock(&shared.stopping);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl DiskShared {
+    fn lock(&self) -> MutexGuard<'_, DiskState> {
+        lock(&self.state)
+    }
+
+    /// Checks if a record is past its retention time and expiry isn't paused
+    fn is_expired(&self, record: &IndexRecord, now: u64) -> bool {
+        !self.expiry_paused.load(Ordering::Relaxed) && record.is_expired(now)
+    }
+
+    fn body_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(BODIES_DIR).join(hash)
+    }
+
+    /// Reads the body of a record, None if its file is gone or doesn't have the recorded size
+    fn read_body(&self, record: &IndexRecord) -> Result<Option<Vec<u8>>, CacheError> {
+        match fs::read(self.body_path(&record.body)) {
+            Ok(body) if body.len() == record.size => Ok(Some(body)),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Loads the index, dropping records whose body is missing,
+    /// then removes body and temporary files nothing refers to
+    fn reload(&self) -> Result<(), CacheError> {
+        let mut records = match fs::read(self.dir.join(INDEX_FILE)) {
+            Ok(data) => match serde_json::from_slice::<IndexFile>(&data) {
+                Ok(index) if index.version == INDEX_VERSION => index.entries,
+                Ok(index) => {
+                    println!("Ignoring disk cache index version {}", index.version);
+                    Vec::new()
+                },
+                Err(e) => {
+                    println!("Ignoring unreadable disk cache index: {}", e);
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        records.retain(|record| {
+            fs::metadata(self.body_path(&record.body))
+                .map(|metadata| metadata.len() as usize == record.size)
+                .unwrap_or(false)
+        });
+
+        // Least recently used first, so the most recent end up at the front of the LRU order
+        records.sort_by_key(|record| record.last_access);
+
+        let mut state = self.lock();
+        for record in records {
+            self.insert_record(&mut state, record);
+        }
+
+        // Bodies and temporary files left behind by a crash or by records dropped above
+        for dir_entry in fs::read_dir(self.dir.join(BODIES_DIR))? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().to_string_lossy().to_string();
+            if !state.body_refs.contains_key(&name) {
+                let _ = fs::remove_file(dir_entry.path());
+            }
+        }
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let dir_entry = dir_entry?;
+            if dir_entry.file_name().to_string_lossy().ends_with(".tmp") {
+                let _ = fs::remove_file(dir_entry.path());
+            }
+        }
+
+        println!("💾 Disk cache: loaded {} entries from {}", state.index.len(), self.dir.display());
+        // Records dropped above are gone from the file too
+        state.dirty = true;
+        Ok(())
+    }
+
+    /// Adds a record to the index, evicting least recently used entries over the cap
+    ///
+    /// - returns false if the record is larger than the whole cap and was dropped
+    fn insert_record(&self, state: &mut DiskState, record: IndexRecord) -> bool {
+        let key = record.key.clone();
+        let weight = record_weight(&record);
+        if !state.tracker.fits(weight) {
+            self.remove_record(state, &key);
+            return false;
+        }
+
+        // Referenced before the old version is released, which may have the same body
+        *state.body_refs.entry(record.body.clone()).or_insert(0) += 1;
+        if let Some(old) = state.index.insert(key.clone(), record) {
+            self.release_body(state, &old.body);
+        }
+
+        for evicted in state.tracker.insert(&key, weight) {
+            if let Some(old) = state.index.remove(&evicted) {
+                self.release_body(state, &old.body);
+            }
+            state.evictions += 1;
+            if let Some(listener) = &state.listener {
+                listener(&evicted);
+            }
+        }
+        true
+    }
+
+    /// Removes a record from the index
+    fn remove_record(&self, state: &mut DiskState, key: &str) -> bool {
+        state.tracker.remove(key);
+        match state.index.remove(key) {
+            Some(record) => {
+                self.release_body(state, &record.body);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Drops one reference to a body, deleting its file with the last one
+    fn release_body(&self, state: &mut DiskState, hash: &str) {
+        let refs = match state.body_refs.get_mut(hash) {
+            Some(refs) => refs,
+            None => return,
+        };
+        *refs -= 1;
+        if *refs == 0 {
+            state.body_refs.remove(hash);
+            let _ = fs::remove_file(self.body_path(hash));
+        }
+    }
+
+    /// Removes records past their retention time, so they stop counting towards the cap
+    fn drop_expired(&self) {
+        let now = now_secs();
+        let mut state = self.lock();
+        let expired: Vec<String> = state.index.values().filter(|record| self.is_expired(record, now)).map(|record| record.key.clone()).collect();
+        for key in &expired {
+            self.remove_record(&mut state, key);
+        }
+        if !expired.is_empty() {
+            state.expirations += expired.len() as u64;
+            state.dirty = true;
+        }
+    }
+
+    /// Writes the index if it changed since the last write, serialising it outside the state lock
+    fn flush_index(&self) -> Result<(), CacheError> {
+        let _flushing = lock(&self.flushing);
+        let entries = {
+            let mut state = self.lock();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.index.values().cloned().collect()
+        };
+
+        let index = IndexFile {
+            version: INDEX_VERSION,
+            entries,
+        };
+        let result = serde_json::to_vec(&index)
+            .map_err(CacheError::from)
+            .and_then(|data| write_atomically(&self.dir.join(INDEX_FILE), &data));
+        if result.is_err() {
+            // Tried again on the next flush
+            self.lock().dirty = true;
+        }
+        result
+    }
+}
+
+impl CacheStore for DiskStore {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<CacheEntry>, CacheError> {
+        let shared = &self.shared;
+        let record = {
+            let mut state = shared.lock();
+            let expired = match state.index.get_mut(key) {
+                Some(record) if shared.is_expired(record, now_secs()) => true,
+                Some(record) => {
+                    // Saved with the next change or on shutdown, reads alone don't rewrite the index
+                    record.last_access = now_secs();
+                    false
+                },
+                None => return Ok(None),
+            };
+            if expired {
+                shared.remove_record(&mut state, key);
+                state.expirations += 1;
+                state.dirty = true;
+                return Ok(None);
+            }
+            state.tracker.record_access(key);
+            state.index[key].clone()
+        };
+
+        // Read outside the lock, an eviction meanwhile shows up as a missing file
+        let body = match shared.read_body(&record)? {
+            Some(body) => body,
+            None => {
+                // Unless the entry was replaced meanwhile, the record is useless without its body
+                let mut state = shared.lock();
+                if state.index.get(key).map(|current| current.body == record.body).unwrap_or(false) {
+                    shared.remove_record(&mut state, key);
+                    state.dirty = true;
+                }
+                return Ok(None);
+            }
+        };
+
+        let mut entry = record.entry;
+        entry.response_data = body;
+        Ok(Some(entry))
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, CacheError> {
+        Ok(self.shared.lock().index.contains_key(key))
+    }
+
+    fn put(&self, key: &str, entry: &CacheEntry, retain_until: Option<u64>) -> Result<(), CacheError> {
+        let shared = &self.shared;
+        let hash = hex(&Sha256::digest(&entry.response_data));
+        let record = IndexRecord {
+            key: key.to_string(),
+            body: hash.clone(),
+            size: entry.response_data.len(),
+            retain_until,
+            last_access: now_secs(),
+            entry: entry.without_body(),
+        };
+
+        {
+            let mut state = shared.lock();
+            if !state.tracker.fits(record_weight(&record)) {
+                println!("Not caching {} on disk: {} bytes exceeds the disk cache size", key, record.size);
+                if shared.remove_record(&mut state, key) {
+                    state.dirty = true;
+                }
+                return Ok(());
+            }
+            // Reference the body while it is written, so no other entry deletes the same file meanwhile
+            *state.body_refs.entry(hash.clone()).or_insert(0) += 1;
+        }
+
+        // The body is in place before the index refers to it, written outside the lock
+        let body_path = shared.body_path(&hash);
+        let written = if body_path.exists() { Ok(()) } else { write_atomically(&body_path, &entry.response_data) };
+
+        let mut state = shared.lock();
+        if written.is_ok() {
+            shared.insert_record(&mut state, record);
+            state.dirty = true;
+        }
+        shared.release_body(&mut state, &hash);
+        written
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        let mut state = self.shared.lock();
+        let removed = self.shared.remove_record(&mut state, key);
+        if removed {
+            state.dirty = true;
+        }
+        Ok(removed)
+    }
+
+    fn clear(&self) -> Result<(), CacheError> {
+        let shared = &self.shared;
+        {
+            let mut state = shared.lock();
+            let keys: Vec<String> = state.index.keys().cloned().collect();
+            for key in keys {
+                shared.remove_record(&mut state, &key);
+            }
+            state.tracker.clear();
+            state.dirty = true;
+        }
+        // An emptied cache shouldn't come back after a crash
+        shared.flush_index()
+    }
+
+    fn scan(&self) -> Result<Vec<String>, CacheError> {
+        // The flusher drops expired records, these are the ones since its last pass
+        let now = now_secs();
+        Ok(self.shared.lock().index.values().filter(|record| !self.shared.is_expired(record, now)).map(|record| record.key.clone()).collect())
+    }
+
+    fn peek(&self, key: &str) -> Result<Option<StoredEntry>, CacheError> {
+        Ok(self.shared.lock().index.get(key).map(IndexRecord::stored_entry))
+    }
+
+    fn export(&self, key: &str) -> Result<Option<(CacheEntry, Option<u64>)>, CacheError> {
+        // Leaves the LRU order and access time alone, unlike get
+        let record = match self.shared.lock().index.get(key) {
+            Some(record) if self.shared.is_expired(record, now_secs()) => return Ok(None),
+            Some(record) => record.clone(),
+            None => return Ok(None),
+        };
+
+        let body = match self.shared.read_body(&record)? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+
+        let mut entry = record.entry;
+        entry.response_data = body;
+        Ok(Some((entry, record.retain_until)))
+    }
+
+    fn entries(&self) -> Result<Vec<StoredEntry>, CacheError> {
+        let now = now_secs();
+        Ok(self.shared.lock().index.values().filter(|record| !self.shared.is_expired(record, now)).map(IndexRecord::stored_entry).collect())
+    }
+
+    fn set_eviction_listener(&self, listener: EvictionListener) {
+        self.shared.lock().listener = Some(listener);
+    }
+
+    fn set_expiry_paused(&self, paused: bool) {
+        self.shared.expiry_paused.store(paused, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> Result<StoreStats, CacheError> {
+        let state = self.shared.lock();
+        let mut stats = StoreStats {
+            entries: state.index.len(),
+            bytes: Some(state.tracker.total_weight()),
+            ..StoreStats::default()
+        };
+        stats.evictions.evictions = state.evictions;
+        stats.evictions.expirations = state.expirations;
+        Ok(stats)
+    }
+
+    fn shutdown(&self) {
+        *lock(&self.shared.stopping) = true;
+        self.shared.wakeup.notify_one();
+        if let Some(handle) = lock(&self.flusher).take() {
+            let _ = handle.join();
+        }
+
+        // Also saves the access times of gets since the last write
+        self.shared.lock().dirty = true;
+        if let Err(e) = self.shared.flush_index() {
+            println!("Failed to save disk cache index: {}", e);
+        }
+    }
+}
+
+/// Bytes an index record is charged: its body plus the key, headers and a fixed overhead
+fn record_weight(record: &IndexRecord) -> usize {
+    let headers: usize = record.entry.headers.iter().map(|(name, value)| name.len() + value.len()).sum();
+    record.size + record.key.len() + headers + ENTRY_OVERHEAD
+}
+
+/// Writes a file under a temporary name, flushes it and renames it into place
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), CacheError> {
+    let temp_name = format!(
+        "{}.{}.{}.tmp",
+        path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(result?)
+}
+
+/// Lowercase hex encoding of a hash
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,453 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+
+// Eviction policies for the L1 cache:
+// EvictionPolicy - which policy HttpCache uses (set in CacheConfig)
+// EvictionTracker - keeps recency/frequency bookkeeping and picks the entries to drop
+
+/// Which entries the L1 cache drops when it is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently used
+    Lru,
+
+    /// Least frequently used, ties broken by recency
+    Lfu,
+
+    /// W-TinyLFU: new entries go to a small LRU window; when the window overflows its victim
+    /// only replaces the main region's victim if a frequency sketch says it is used more often
+    TinyLfu,
+}
+
+const NIL: usize = usize::MAX;
+
+struct Node {
+    key: String,
+    prev: usize,
+    next: usize,
+}
+
+/// Doubly linked list stored in a slab, O(1) push/touch/remove/pop
+struct LruList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    index: HashMap<String, usize>,
+    /// Most recently used
+    head: usize,
+    /// Least recently used
+    tail: usize,
+}
+
+impl LruList {
+    fn new() -> Self {
+        LruList {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Adds a key as most recently used
+    fn push_front(&mut self, key: &str) {
+        let node = Node {
+            key: key.to_string(),
+            prev: NIL,
+            next: self.head,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+                idx
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        if self.head != NIL {
+            self.nodes[self.head].prev = idx;
+        }
+        self.head = idx;
+        if self.tail == NIL {
+            self.tail = idx;
+        }
+        self.index.insert(key.to_string(), idx);
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = (self.nodes[idx].prev, self.nodes[idx].next);
+        if prev != NIL {
+            self.nodes[prev].next = next;
+        } else {
+            self.head = next;
+        }
+        if next != NIL {
+            self.nodes[next].prev = prev;
+        } else {
+            self.tail = prev;
+        }
+    }
+
+    /// Marks a key as most recently used
+    fn touch(&mut self, key: &str) -> bool {
+        let idx = match self.index.get(key) {
+            Some(&idx) => idx,
+            None => return false,
+        };
+        if idx != self.head {
+            self.unlink(idx);
+            self.nodes[idx].prev = NIL;
+            self.nodes[idx].next = self.head;
+            self.nodes[self.head].prev = idx;
+            self.head = idx;
+        }
+        true
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self.index.remove(key) {
+            Some(idx) => {
+                self.unlink(idx);
+                self.nodes[idx].key.clear();
+                self.free.push(idx);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Least recently used key
+    fn back(&self) -> Option<&str> {
+        if self.tail == NIL {
+            None
+        } else {
+            Some(&self.nodes[self.tail].key)
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = LruList::new();
+    }
+}
+
+/// Orders keys by (frequency, last access) for LFU
+struct LfuIndex {
+    order: BTreeSet<(u64, u64, String)>,
+    meta: HashMap<String, (u64, u64)>,
+    tick: u64,
+}
+
+impl LfuIndex {
+    fn new() -> Self {
+        LfuIndex {
+            order: BTreeSet::new(),
+            meta: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn insert(&mut self, key: &str) {
+        self.tick += 1;
+        self.meta.insert(key.to_string(), (1, self.tick));
+        self.order.insert((1, self.tick, key.to_string()));
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some((frequency, tick)) = self.meta.get(key).copied() {
+            self.order.remove(&(frequency, tick, key.to_string()));
+            self.tick += 1;
+            self.meta.insert(key.to_string(), (frequency + 1, self.tick));
+            self.order.insert((frequency + 1, self.tick, key.to_string()));
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((frequency, tick)) = self.meta.remove(key) {
+            self.order.remove(&(frequency, tick, key.to_string()));
+        }
+    }
+
+    /// Least frequently used key other than `protected` (unless it is the only one)
+    fn victim(&self, protected: &str) -> Option<&str> {
+        let mut keys = self.order.iter().map(|(_, _, key)| key.as_str());
+        let first = keys.next()?;
+        if first != protected {
+            return Some(first);
+        }
+        keys.next().or(Some(first))
+    }
+
+    fn clear(&mut self) {
+        *self = LfuIndex::new();
+    }
+}
+
+/// Count-min sketch of access frequencies with 4-bit counters that are halved periodically,
+/// so old popularity fades out
+struct FrequencySketch {
+    table: Vec<u8>,
+    width: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+const SKETCH_ROWS: u64 = 4;
+
+impl FrequencySketch {
+    fn new(width: usize) -> Self {
+        let width = width.clamp(16, 1 << 16).next_power_of_two();
+        FrequencySketch {
+            table: vec![0; width * SKETCH_ROWS as usize],
+            width,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn slot(&self, key: &str, row: u64) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        row as usize * self.width + (hasher.finish() as usize & (self.width - 1))
+    }
+
+    fn increment(&mut self, key: &str) {
+        for row in 0..SKETCH_ROWS {
+            let slot = self.slot(key, row);
+            if self.table[slot] < 15 {
+                self.table[slot] += 1;
+            }
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            for counter in self.table.iter_mut() {
+                *counter /= 2;
+            }
+            self.additions /= 2;
+        }
+    }
+
+    fn frequency(&self, key: &str) -> u8 {
+        (0..SKETCH_ROWS)
+            .map(|row| self.table[self.slot(key, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn clear(&mut self) {
+        self.table.iter_mut().for_each(|counter| *counter = 0);
+        self.additions = 0;
+    }
+}
+
+///
+/// Bookkeeping for one eviction policy over weighted keys
+///
+/// The owner stores the values; the tracker only decides which keys have to go
+///
+pub struct EvictionTracker {
+    policy: EvictionPolicy,
+
+    /// Maximum total weight
+    capacity: usize,
+
+    weights: HashMap<String, usize>,
+    total_weight: usize,
+
+    /// LRU order (LRU policy, and the main region of TinyLFU)
+    main: LruList,
+
+    /// Admission window (TinyLFU only)
+    window: LruList,
+    window_weight: usize,
+
+    /// Frequency order (LFU only)
+    lfu: LfuIndex,
+
+    /// Access frequencies (TinyLFU only)
+    sketch: Option<FrequencySketch>,
+}
+
+impl EvictionTracker {
+    /// Creates a tracker for the given policy and total weight capacity
+    pub fn new(policy: EvictionPolicy, capacity: usize) -> Self {
+        // Capacity is in bytes, size the sketch for entries of ~16 KiB on average
+        let expected_entries = capacity / 16384;
+        EvictionTracker {
+            policy,
+            capacity,
+            weights: HashMap::new(),
+            total_weight: 0,
+            main: LruList::new(),
+            window: LruList::new(),
+            window_weight: 0,
+            lfu: LfuIndex::new(),
+            sketch: match policy {
+                EvictionPolicy::TinyLfu => Some(FrequencySketch::new(expected_entries)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Total weight of the tracked keys
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Whether an entry of this weight can be stored at all
+    pub fn fits(&self, weight: usize) -> bool {
+        weight <= self.capacity
+    }
+
+    /// Records a cache hit on a key
+    pub fn record_access(&mut self, key: &str) {
+        if let Some(sketch) = self.sketch.as_mut() {
+            sketch.increment(key);
+        }
+        if !self.weights.contains_key(key) {
+            return;
+        }
+
+        match self.policy {
+            EvictionPolicy::Lru => {
+                self.main.touch(key);
+            },
+            EvictionPolicy::Lfu => self.lfu.touch(key),
+            EvictionPolicy::TinyLfu => {
+                if !self.window.touch(key) {
+                    self.main.touch(key);
+                }
+            }
+        }
+    }
+
+    /// Adds (or updates) a key and evicts until the total weight fits the capacity
+    ///
+    /// - returns the keys that have to be dropped, which includes `key` itself if it was not admitted
+    pub fn insert(&mut self, key: &str, weight: usize) -> Vec<String> {
+        // Larger than the whole capacity: dropped right away instead of flushing every other entry first
+        if !self.fits(weight) {
+            self.remove(key);
+            return vec![key.to_string()];
+        }
+
+        if let Some(old_weight) = self.weights.insert(key.to_string(), weight) {
+            // Update of an existing key
+            self.total_weight = self.total_weight - old_weight + weight;
+            if self.window.contains(key) {
+                self.window_weight = self.window_weight - old_weight + weight;
+            }
+            self.record_access(key);
+        } else {
+            self.total_weight += weight;
+            if let Some(sketch) = self.sketch.as_mut() {
+                sketch.increment(key);
+            }
+            match self.policy {
+                EvictionPolicy::Lru => self.main.push_front(key),
+                EvictionPolicy::Lfu => self.lfu.insert(key),
+                EvictionPolicy::TinyLfu => {
+                    self.window.push_front(key);
+                    self.window_weight += weight;
+                }
+            }
+        }
+
+        let mut evicted = Vec::new();
+        if self.policy == EvictionPolicy::TinyLfu {
+            self.overflow_window(&mut evicted);
+        }
+
+        // Plain eviction (also covers a single entry larger than the TinyLFU window)
+        while self.total_weight > self.capacity {
+            let victim = match self.policy {
+                EvictionPolicy::Lru => self.main.back(),
+                // A new entry always starts with the lowest count, so it is spared
+                EvictionPolicy::Lfu => self.lfu.victim(key),
+                EvictionPolicy::TinyLfu => self.main.back().or(self.window.back()),
+            };
+            match victim.map(|victim| victim.to_string()) {
+                Some(victim) => {
+                    self.remove(&victim);
+                    evicted.push(victim);
+                },
+                None => break,
+            }
+        }
+
+        evicted
+    }
+
+    /// Moves TinyLFU window victims to the main region, dueling with the main victim when it's full
+    fn overflow_window(&mut self, evicted: &mut Vec<String>) {
+        let window_capacity = (self.capacity / 100).max(1);
+
+        while self.window_weight > window_capacity {
+            let candidate = match self.window.back() {
+                Some(candidate) => candidate.to_string(),
+                None => break,
+            };
+            let weight = self.weights[&candidate];
+            self.window.remove(&candidate);
+            self.window_weight -= weight;
+            self.main.push_front(&candidate);
+
+            let candidate_frequency = self.frequency(&candidate);
+
+            // Make room in the main region, the candidate has to beat every victim it replaces
+            while self.total_weight > self.capacity {
+                let victim = match self.main.back() {
+                    Some(victim) if victim != candidate => victim.to_string(),
+                    _ => break,
+                };
+                let loser = if candidate_frequency > self.frequency(&victim) { victim } else { candidate.clone() };
+                self.remove(&loser);
+                evicted.push(loser.clone());
+                if loser == candidate {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Estimated access frequency from the TinyLFU sketch
+    fn frequency(&self, key: &str) -> u8 {
+        self.sketch.as_ref().map(|sketch| sketch.frequency(key)).unwrap_or(0)
+    }
+
+    /// Stops tracking a key
+    pub fn remove(&mut self, key: &str) {
+        let weight = match self.weights.remove(key) {
+            Some(weight) => weight,
+            None => return,
+        };
+        self.total_weight -= weight;
+        if self.window.remove(key) {
+            self.window_weight -= weight;
+        }
+        self.main.remove(key);
+        self.lfu.remove(key);
+    }
+
+    /// Forgets all keys (and access history)
+    pub fn clear(&mut self) {
+        self.weights.clear();
+        self.total_weight = 0;
+        self.main.clear();
+        self.window.clear();
+        self.window_weight = 0;
+        self.lfu.clear();
+        if let Some(sketch) = self.sketch.as_mut() {
+            sketch.clear();
+        }
+    }
+}
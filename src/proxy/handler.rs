@@ -1,19 +1,23 @@
 use std::net::TcpStream;
 use std::io::Read;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use super::http::forward_http_request;
 use super::https::handle_https_tunnel;
-use crate::utils::parsing::extract_host;
+use super::purge::{handle_invalidation, is_invalidation_method};
+use crate::utils::parsing::{extract_host, parse_http_request, request_url};
 
-use crate::utils::responses::send_403_forbidden;
+use crate::utils::block_page::BlockInfo;
+use crate::utils::responses::{ErrorResponse, Status};
 
 use crate::utils::host_filtering::Blacklist;
 use crate::proxy::cache::HttpCache;
+use crate::utils::stats::ProxyStats;
+use crate::proxy::config::ProxyConfig;
 
 ///  Handles HTTP and HTTPS requests
-
-pub fn handle_client_connection(mut client_stream: TcpStream, blacklist: Arc<Blacklist>, cache: Arc<HttpCache>) {
+pub fn handle_client_connection(mut client_stream: TcpStream, blacklist: Arc<Blacklist>, cache: Arc<HttpCache>, stats: Arc<ProxyStats>, config: Arc<ProxyConfig>) {
     let mut buffer = [0u8; 8192];
 
     match client_stream.read(&mut buffer) {
@@ -23,35 +27,12 @@ pub fn handle_client_connection(mut client_stream: TcpStream, blacklist: Arc<Bla
                 return;
             }
 
-            let request_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-            println!("\nPeeked HTTP/S request:\n{}", request_str);
-
-            // Extract host first
-            match extract_host(&request_str) {
-                Some(host) => {
-                    // Check blacklist
-                    if blacklist.has(&host) {
-                        println!("Host '{}' is blacklisted", host);
-                        send_403_forbidden(&mut client_stream);
-
-                        // Close client connection
-                        let _ = client_stream.shutdown(std::net::Shutdown::Both);
-                        return;
-                    }
-
-                    // Process based on request type
-                    if request_str.starts_with("CONNECT") {
-                        let _ = handle_https_tunnel(&request_str, client_stream);
-                    } else {
-                        forward_http_request(host, &buffer[..bytes_read], client_stream, Arc::clone(&cache));
-                    }
-                },
-                None => {
-                    println!("Failed to extract host from request");
-                    // Maybe send a 400 Bad Request response here
-                    let _ = client_stream.shutdown(std::net::Shutdown::Both);
-                }
+            // Any stage can fail with an error response, which is rendered here
+            if let Err(error) = handle_request(&buffer[..bytes_read], &mut client_stream, &blacklist, &cache, &stats, &config) {
+                println!("Responding with {}", error);
+                error.send(&mut client_stream, &config.error_templates);
             }
+            let _ = client_stream.shutdown(std::net::Shutdown::Both);
         },
         Err(e) => {
             println!("Failed to read from stream: {}", e);
@@ -59,3 +40,59 @@ pub fn handle_client_connection(mut client_stream: TcpStream, blacklist: Arc<Bla
         }
     }
 }
+
+/// Routes a single request to the blacklist, the tunnel, PURGE/BAN handling or the HTTP forwarder
+fn handle_request(buffer: &[u8], client_stream: &mut TcpStream, blacklist: &Blacklist, cache: &Arc<HttpCache>, stats: &Arc<ProxyStats>, config: &Arc<ProxyConfig>) -> Result<(), ErrorResponse> {
+    let request_str = String::from_utf8_lossy(buffer);
+    println!("\nPeeked HTTP/S request:\n{}", request_str);
+
+    // Extract host first
+    let host = match extract_host(&request_str) {
+        Some(host) => host,
+        None => {
+            println!("Failed to extract host from request");
+            return Err(ErrorResponse::new(Status::BadRequest).with_message("Missing or invalid Host"));
+        }
+    };
+
+    // Check blacklist
+    if let Some(rule) = blacklist.matching_rule(&host) {
+        println!("Host '{}' is blacklisted", host);
+        if request_str.starts_with("CONNECT") {
+            return Err(ErrorResponse::new(Status::Forbidden).with_message("Access Denied: Blacklisted"));
+        }
+        return Err(block_page(client_stream, &request_str, host, rule, config));
+    }
+
+    // Process based on request type
+    if request_str.starts_with("CONNECT") {
+        return handle_https_tunnel(&request_str, client_stream, Arc::clone(stats), &config.timeouts);
+    }
+    if let Some(request) = parse_http_request(&request_str).filter(|request| is_invalidation_method(&request.method)) {
+        return handle_invalidation(&request, &host, client_stream, cache, config);
+    }
+    forward_http_request(host, buffer, client_stream, Arc::clone(cache), Arc::clone(stats), Arc::clone(config))
+}
+
+/// Renders the configured block page for a blocked plain HTTP request
+fn block_page(client_stream: &TcpStream, request_str: &str, host: String, rule: String, config: &ProxyConfig) -> ErrorResponse {
+    let forbidden = ErrorResponse::new(Status::Forbidden).with_message("Access Denied: Blacklisted");
+    let request = match parse_http_request(request_str) {
+        Some(request) => request,
+        None => return forbidden,
+    };
+
+    let info = BlockInfo {
+        url: request_url(&request.path, &host),
+        host,
+        rule,
+        client_ip: client_stream
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|_| "unknown".to_string()),
+        timestamp: httpdate::fmt_http_date(SystemTime::now()),
+    };
+
+    let (content_type, body) = config.block_page.render(&info, &request.headers);
+    forbidden.with_body(content_type, body)
+}
@@ -1,82 +1,342 @@
-use std::net::TcpStream;
-use std::io::{Write, Read};
+use std::collections::HashMap;
+use std::io::Write;
 use std::sync::Arc;
+use std::thread;
 use std::time::Instant;  // Import timing
 
 use crate::utils::parsing::parse_http_request;
 use crate::utils::parsing::parse_http_response;
-use crate::proxy::cache::HttpCache;
+use crate::utils::parsing::{add_headers, get_header, request_url, resolve_same_host_url, strip_headers};
+use crate::utils::responses::{ErrorResponse, Status};
+use crate::proxy::cache::{CacheEntry, FetchRole, HttpCache};
+use crate::proxy::config::ProxyConfig;
+use crate::proxy::upstream::{self, UpstreamError, UpstreamResponse};
+use crate::utils::stats::ProxyStats;
 
 /// # Forwards HTTP requests to real servers
-
-pub fn forward_http_request(host: String, buffer: &[u8], mut client_stream: TcpStream, cache: Arc<HttpCache>) {
+///
+/// The response goes to `client_stream`, a client connection or a sink when warming up the cache
+pub fn forward_http_request<W: Write>(host: String, buffer: &[u8], client_stream: &mut W, cache: Arc<HttpCache>, stats: Arc<ProxyStats>, config: Arc<ProxyConfig>) -> Result<(), ErrorResponse> {
     let request_str = String::from_utf8_lossy(buffer);
-    let request_headers = parse_http_request(&request_str).unwrap().headers;
+    let (method, url, request_headers) = match parse_http_request(&request_str) {
+        Some(request) => (request.method, request_url(&request.path, &host), request.headers),
+        None => return Err(ErrorResponse::new(Status::BadRequest).with_message("Malformed request line")),
+    };
 
     println!("Forwarding HTTP request to: {}", host);
 
+    // Responses are cached per URL, and only for GET
+    let cacheable = method.eq_ignore_ascii_case("GET");
+
+    if config.is_offline() {
+        return serve_offline(&method, &url, cacheable, client_stream, &cache);
+    }
+
     let start_total = Instant::now();  // Start total timing
     // Measure Cache Lookup Time
     let start_cache = Instant::now();
-    if let Some(cached_entry) = cache.get(&host, &request_headers) {
+    if let Some(cached_entry) = cacheable.then(|| cache.get(&url, &request_headers)).flatten() {
         let cache_time = start_cache.elapsed();
-        println!("Cache hit for {} (lookup time: {:.2?})", host, cache_time);
+        println!("Cache hit for {} (lookup time: {:.2?})", url, cache_time);
+
+        // Expired but within stale-while-revalidate: serve it now and refresh in the background
+        let response = if cached_entry.is_valid() {
+            cached_entry.response_data.clone()
+        } else {
+            spawn_revalidation(&host, &url, buffer, &cached_entry, &cache, &stats, &config);
+            add_headers(&cached_entry.response_data, &[
+                ("Age", cached_entry.age().to_string()),
+                ("Warning", "110 roxy \"Response is Stale\"".to_string()),
+            ])
+        };
 
         let start_send_cache = Instant::now();
-        if let Err(e) = client_stream.write_all(&cached_entry.response_data) {
-            println!("Failed to forward cached response: {}", e);
+        match client_stream.write_all(&response) {
+            Ok(()) => cache.record_served(&url, response.len()),
+            Err(e) => println!("Failed to forward cached response: {}", e),
         }
         let send_cache_time = start_send_cache.elapsed();
         println!("Cached response sent in {:.2?}", send_cache_time);
 
         let total_time = start_total.elapsed();
         println!("Total request time (cache hit): {:.2?}", total_time);
-        return;
+        return Ok(());
     }
     let cache_time = start_cache.elapsed();
     println!("Cache miss (lookup time: {:.2?})", cache_time);
 
+    // Collapse concurrent misses for the same key into a single origin fetch
+    let leader = if cacheable {
+        match cache.join_fetch(&url) {
+            FetchRole::Leader(leader) => Some(leader),
+            FetchRole::Follower(follower) => {
+                println!("Waiting for in-flight fetch of {}", url);
+                if let Some(entry) = follower.wait(config.timeouts.total) {
+                    match client_stream.write_all(&entry.response_data) {
+                        Ok(()) => cache.record_served(&url, entry.response_data.len()),
+                        Err(e) => println!("Failed to forward coalesced response: {}", e),
+                    }
+                    println!("Total request time (coalesced): {:.2?}", start_total.elapsed());
+                    return Ok(());
+                }
+                // Not cacheable or the fetch failed, go to the origin ourselves
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     // Measure Request Forwarding Time
     let start_forward = Instant::now();
-    match TcpStream::connect(&host) {
-        Ok(mut server_stream) => {
-            if let Err(e) = server_stream.write_all(buffer) {
-                println!("Failed to send request to server: {}", e);
-                return;
+    let mut response = match fetch_from_origin(&host, &method, buffer, &stats, &config) {
+        Ok(response) => response,
+        Err(e) => {
+            println!("Failed to fetch from real server: {}", e);
+            stats.record_upstream_failure(e.failure());
+            if let Some(stale) = cacheable.then(|| serve_stale(&url, client_stream, &cache)).flatten() {
+                if let Some(leader) = leader {
+                    leader.complete_stale(stale);
+                }
+                return Ok(());
             }
+            return Err(e.to_response(&host));
+        }
+    };
+    println!("Received response head from server in {:.2?}", start_forward.elapsed());
 
-            let forward_time = start_forward.elapsed();
-            println!("Request forwarded in {:.2?}", forward_time);
-
-            let mut server_response_buffer = [0u8; 8192 * 64];
+    let response_str = String::from_utf8_lossy(response.head());
+    let parsed_response = match parse_http_response(&response_str) {
+        Some(response) => response,
+        None => return Err(ErrorResponse::new(Status::BadGateway).with_message(format!("Invalid response from {}", host))),
+    };
 
-            let start_response = Instant::now();
-            match server_stream.read(&mut server_response_buffer) {
-                Ok(response_size) => {
-                    let response_time = start_response.elapsed();
-                    println!("Received response from server in {:.2?}", response_time);
+    // Prefer a stale copy over passing on a server error
+    if cacheable && parsed_response.status_code >= 500 {
+        if let Some(stale) = serve_stale(&url, client_stream, &cache) {
+            if let Some(leader) = leader {
+                leader.complete_stale(stale);
+            }
+            return Ok(());
+        }
+    }
 
-                    let response_str = String::from_utf8_lossy(&server_response_buffer[..response_size]);
+    if !cacheable && !upstream::is_safe(&method) && (200..400).contains(&parsed_response.status_code) {
+        invalidate_after_unsafe(&url, &parsed_response.headers, &cache);
+    }
 
-                    let parsed_response = parse_http_response(&response_str);
-                    let response_data = server_response_buffer[..response_size].to_vec();
+    // Stream the response to the client as it arrives, keeping a copy for the cache while it fits
+    let start_send_client = Instant::now();
+    let max_object_size = config.cache.max_object_size;
+    let mut cache_copy = cacheable.then(Vec::new);
+    let mut client_open = true;
+    let result = loop {
+        let chunk = match response.next_chunk() {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        };
+        if client_open {
+            if let Err(e) = client_stream.write_all(chunk) {
+                println!("Failed to forward response: {}", e);
+                client_open = false;
+            }
+        }
+        match cache_copy.as_mut() {
+            Some(copy) if copy.len() + chunk.len() <= max_object_size => copy.extend_from_slice(chunk),
+            Some(_) => {
+                cache.reject_oversized(&url);
+                cache_copy = None;
+            },
+            None => {},
+        }
+        // Nobody needs the rest
+        if !client_open && cache_copy.is_none() {
+            break Ok(());
+        }
+    };
+    println!("Response sent to client in {:.2?}", start_send_client.elapsed());
 
-                    // Store in cache
-                    let _ = cache.put(&host, response_data.clone(), parsed_response.unwrap().headers.clone());
+    if let Err(e) = result {
+        // Part of it has been sent already, so there is no error page; followers fetch it themselves
+        println!("Response from {} was cut short: {}", host, e);
+        stats.record_upstream_failure(e.failure());
+        return Ok(());
+    }
 
-                    let start_send_client = Instant::now();
-                    if let Err(e) = client_stream.write_all(&response_data) {
-                        println!("Failed to forward response: {}", e);
-                    }
-                    let send_client_time = start_send_client.elapsed();
-                    println!("Response sent to client in {:.2?}", send_client_time);
-                }
-                Err(e) => println!("Failed to read server response: {}", e),
-            }
+    if let Some(response_data) = cache_copy {
+        // Release the threads waiting for this response
+        if let Some(leader) = leader {
+            leader.complete(CacheEntry::new(response_data.clone(), parsed_response.headers.clone()));
         }
-        Err(e) => println!("Failed to connect to real server: {}", e),
+
+        // Store in cache
+        let _ = cache.put(&url, response_data, parsed_response.headers);
     }
 
     let total_time = start_total.elapsed();
     println!("Total request time: {:.2?}", total_time);
+    Ok(())
+}
+
+/// Starts a background refresh of an entry served within its stale-while-revalidate window
+///
+/// Only one revalidation per key runs at a time, concurrent hits skip it
+fn spawn_revalidation(host: &str, url: &str, request: &[u8], entry: &CacheEntry, cache: &Arc<HttpCache>, stats: &Arc<ProxyStats>, config: &Arc<ProxyConfig>) {
+    let guard = match cache.begin_revalidation(url) {
+        Some(guard) => guard,
+        None => return,
+    };
+
+    // Ask the origin whether our copy is still current
+    let request = strip_headers(request, &["If-None-Match", "If-Modified-Since"]);
+    let mut validators = Vec::new();
+    if let Some(etag) = &entry.etag {
+        validators.push(("If-None-Match", etag.clone()));
+    }
+    if let Some(last_modified) = &entry.last_modified {
+        validators.push(("If-Modified-Since", last_modified.clone()));
+    }
+    let request = add_headers(&request, &validators);
+
+    let host = host.to_string();
+    let url = url.to_string();
+    let entry = entry.clone();
+    let cache = Arc::clone(cache);
+    let stats = Arc::clone(stats);
+    let config = Arc::clone(config);
+
+    thread::spawn(move || {
+        let _guard = guard;
+        println!("Revalidating {} in the background", url);
+
+        let response = fetch_from_origin(&host, "GET", &request, &stats, &config)
+            .and_then(|response| response.read_to_end(config.cache.max_object_size));
+        let response_data = match response {
+            Ok(Some(response_data)) => response_data,
+            Ok(None) => {
+                cache.reject_oversized(&url);
+                return;
+            },
+            Err(e) => {
+                println!("Background revalidation of {} failed: {}", url, e);
+                stats.record_upstream_failure(e.failure());
+                return;
+            }
+        };
+
+        let response_str = String::from_utf8_lossy(&response_data);
+        let result = match parse_http_response(&response_str) {
+            Some(response) if response.status_code == 304 => cache.freshen(&url, entry, response.headers),
+            Some(response) if response.status_code < 500 => cache.put(&url, response_data.clone(), response.headers),
+            _ => return,
+        };
+        if let Err(e) = result {
+            println!("Failed to store revalidated response for {}: {}", url, e);
+        }
+    });
+}
+
+/// Sends an expired cache entry that is still within its stale-if-error window
+///
+/// - returns the entry as it was sent, for the threads waiting on this fetch, or None if there was no stale copy
+fn serve_stale<W: Write>(url: &str, client_stream: &mut W, cache: &HttpCache) -> Option<CacheEntry> {
+    let mut entry = cache.get_stale(url)?;
+
+    println!("Serving stale cached response for {}", url);
+    let mut headers = vec![("Age", entry.age().to_string())];
+    if !entry.is_valid() {
+        headers.push(("Warning", "110 roxy \"Response is Stale\"".to_string()));
+        headers.push(("Warning", "111 roxy \"Revalidation Failed\"".to_string()));
+    }
+    entry.response_data = add_headers(&entry.response_data, &headers);
+    match client_stream.write_all(&entry.response_data) {
+        Ok(()) => cache.record_served(url, entry.response_data.len()),
+        Err(e) => println!("Failed to forward stale response: {}", e),
+    }
+    Some(entry)
+}
+
+/// Answers a request in offline mode, from whatever the cache holds and without contacting the origin
+fn serve_offline<W: Write>(method: &str, url: &str, cacheable: bool, client_stream: &mut W, cache: &HttpCache) -> Result<(), ErrorResponse> {
+    if !cacheable {
+        return Err(ErrorResponse::new(Status::GatewayTimeout).with_message(format!("{} requests can't reach the origin in offline mode", method)));
+    }
+    let entry = match cache.get_offline(url) {
+        Some(entry) => entry,
+        None => return Err(ErrorResponse::new(Status::GatewayTimeout).with_message(format!("{} is not in the cache (offline mode)", url))),
+    };
+
+    println!("Serving {} from the cache (offline mode)", url);
+    let mut headers = vec![("Age", entry.age().to_string())];
+    if !entry.is_valid() {
+        headers.push(("Warning", "110 roxy \"Response is Stale\"".to_string()));
+        headers.push(("Warning", "112 roxy \"Disconnected Operation\"".to_string()));
+    }
+    let response = add_headers(&entry.response_data, &headers);
+    match client_stream.write_all(&response) {
+        Ok(()) => cache.record_served(url, response.len()),
+        Err(e) => println!("Failed to forward cached response: {}", e),
+    }
+    Ok(())
+}
+
+/// Invalidates the cached responses an unsafe request may have changed (RFC 9111 §4.4):
+/// the request URL and the same-host URLs in Location and Content-Location
+fn invalidate_after_unsafe(url: &str, response_headers: &HashMap<String, String>, cache: &HttpCache) {
+    let mut urls = vec![url.to_string()];
+    for header in ["location", "content-location"] {
+        if let Some(target) = get_header(response_headers, header).and_then(|value| resolve_same_host_url(url, value)) {
+            if !urls.contains(&target) {
+                urls.push(target);
+            }
+        }
+    }
+
+    for url in urls {
+        match cache.invalidate(&url) {
+            Ok(true) => println!("Invalidated cached response for {}", url),
+            Ok(false) => {},
+            Err(e) => println!("Failed to invalidate cached response for {}: {}", url, e),
+        }
+    }
+}
+
+/// Sends the request to the origin and reads the response head within the configured timeouts
+///
+/// Idempotent requests are retried with backoff when the connection fails before any response
+/// byte arrived. The caller streams the body after this returns, so a retry never follows
+/// bytes the client has already seen.
+fn fetch_from_origin(host: &str, method: &str, request: &[u8], stats: &ProxyStats, config: &ProxyConfig) -> Result<UpstreamResponse, UpstreamError> {
+    let started = Instant::now();
+    let mut addrs = upstream::resolve(host, config.timeouts.dns)?;
+
+    let max_retries = if upstream::is_idempotent(method) { config.retry.max_retries } else { 0 };
+    let mut retry = 0;
+
+    loop {
+        let result = upstream::connect_addrs(&addrs, &config.timeouts, started).and_then(|mut server_stream| {
+            upstream::write_request(&mut server_stream, request, &config.timeouts, started)?;
+            upstream::read_response_head(server_stream, method, &config.timeouts, started)
+        });
+
+        let error = match result {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+
+        let backoff = config.retry.backoff(retry);
+        if retry >= max_retries || !error.is_retryable() || started.elapsed() + backoff >= config.timeouts.total {
+            return Err(error);
+        }
+
+        println!("Upstream attempt {} for {} failed ({}), retrying in {:.2?}", retry + 1, host, error, backoff);
+        stats.record_upstream_failure(error.failure());
+        stats.record_upstream_retry();
+        thread::sleep(backoff);
+
+        // Start the next attempt with a different address
+        addrs.rotate_left(1);
+        retry += 1;
+    }
 }
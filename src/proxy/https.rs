@@ -1,116 +1,186 @@
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 use std::io::Write;
+use std::sync::Arc;
 use std::thread;
 use std::io;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::io::Read;
 use std::io::ErrorKind;
 
+use crate::utils::logging::log_tunnel;
 use crate::utils::parsing::extract_host;
+use crate::utils::responses::{ErrorResponse, Status};
+use crate::proxy::upstream::{self, UpstreamTimeouts};
+use crate::utils::stats::{CloseReason, ProxyStats, TunnelRecord};
 
 /// # Handles HTTPS CONNECT tunneling
+pub fn handle_https_tunnel(request_str: &str, client_stream: &mut TcpStream, stats: Arc<ProxyStats>, timeouts: &UpstreamTimeouts) -> Result<(), ErrorResponse> {
+    let host = match extract_host(request_str) {
+        Some(host) => host,
+        None => {
+            println!("Invalid CONNECT request: no host found");
+            return Err(ErrorResponse::new(Status::BadRequest).with_message("CONNECT request without a host"));
+        }
+    };
+    println!("Handling CONNECT request to {}", host);
 
-pub fn handle_https_tunnel(request_str: &str, mut client_stream: TcpStream) -> io::Result<()> {
-    if let Some(host) = extract_host(request_str) {
-        println!("Handling CONNECT request to {}", host);
+    let server_stream = upstream::connect(&host, timeouts).map_err(|e| {
+        println!("Failed to connect to HTTPS server: {}", e);
+        stats.record_upstream_failure(e.failure());
+        e.to_response(&host)
+    })?;
 
-        match TcpStream::connect(&host) {
-            Ok(server_stream) => {
-                // Send success response to client
-                client_stream.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
-                println!("Established tunnel to {}", &host);
+    // Once the tunnel is established errors can no longer be reported to the client
+    if let Err(e) = run_tunnel(host, client_stream, server_stream, &stats) {
+        println!("Tunnel error: {}", e);
+    }
+    Ok(())
+}
 
-                // Set non-blocking for both streams
-                client_stream.set_nonblocking(true)?;
-                server_stream.set_nonblocking(true)?;
+/// Confirms the tunnel to the client and pipes data until it closes
+fn run_tunnel(host: String, client_stream: &mut TcpStream, server_stream: TcpStream, stats: &ProxyStats) -> io::Result<()> {
+    // Send success response to client
+    client_stream.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
+    println!("Established tunnel to {}", &host);
 
-                // Create separate streams for each direction
-                tunnel_data(client_stream, server_stream)
-            },
-            Err(e) => {
-                println!("Failed to connect to HTTPS server: {}", e);
-                client_stream.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")?;
-                Ok(())
-            }
-        }
-    } else {
-        println!("Invalid CONNECT request: no host found");
-        client_stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")?;
-        Ok(())
-    }
+    let client = client_stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    // Set non-blocking for both streams
+    client_stream.set_nonblocking(true)?;
+    server_stream.set_nonblocking(true)?;
+
+    let _active = stats.tunnel_opened();
+    let start = Instant::now();
+
+    // Create separate streams for each direction
+    let (bytes_up, bytes_down, time_to_first_byte, close_reason) = tunnel_data(client_stream.try_clone()?, server_stream, start)?;
+
+    let record = TunnelRecord {
+        host,
+        client,
+        bytes_up,
+        bytes_down,
+        duration: start.elapsed(),
+        time_to_first_byte,
+        close_reason,
+    };
+    log_tunnel(&record);
+    stats.record_tunnel(&record);
+    Ok(())
 }
 
-fn tunnel_data(mut client_stream: TcpStream, mut server_stream: TcpStream) -> io::Result<()> {
-    let mut buffer = [0; 8192];
+/// Which end of the tunnel a copy loop reads from
+#[derive(Clone, Copy)]
+enum Side {
+    Client,
+    Server,
+}
 
-    // Clone the streams for the two threads
-    let mut client_read = client_stream.try_clone()?;
-    let mut server_read = server_stream.try_clone()?;
+/// Copies data from `from` to `to` until either end closes
+///
+/// - returns the number of bytes copied, the time of the first byte read and why copying stopped
+fn copy_until_closed(mut from: TcpStream, mut to: TcpStream, side: Side, start: Instant) -> (u64, Option<Duration>, CloseReason) {
+    let mut buffer = [0; 8192];
+    let mut bytes = 0u64;
+    let mut first_byte = None;
 
-    // Create a thread to handle client -> server data
-    let client_to_server = thread::spawn(move || {
-        let mut buffer = [0; 8192];
-        loop {
-            match client_read.read(&mut buffer) {
-                Ok(0) => break, // Connection closed
-                Ok(n) => {
-                    if let Err(e) = server_stream.write_all(&buffer[0..n]) {
-                        println!("Error writing to server: {}", e);
-                        break;
-                    }
-                },
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(10)); // Wait and retry
-                    continue;
-                },
-                Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
-                    println!("Client disconnected, stopping write.");
-                    break;
-                },
-                Err(ref e) if e.kind() == ErrorKind::ConnectionReset => {
-                    println!("Client closed connection abruptly.");
-                    break;
-                },
-                Err(e) => {
-                    println!("Error reading from client: {}", e);
-                    break;
-                }
-            }
-        }
-    });
+    let (closed, reset) = match side {
+        Side::Client => (CloseReason::ClientClosed, CloseReason::ClientReset),
+        Side::Server => (CloseReason::ServerClosed, CloseReason::ServerReset),
+    };
+    // Write errors belong to the other end
+    let write_reset = match side {
+        Side::Client => CloseReason::ServerReset,
+        Side::Server => CloseReason::ClientReset,
+    };
 
-    // Handle server -> client data in the current thread
-    loop {
-        match server_read.read(&mut buffer) {
-            Ok(0) => break, // Connection closed
+    let reason = loop {
+        match from.read(&mut buffer) {
+            Ok(0) => {
+                // Connection closed, pass the half-close on to the other end
+                let _ = to.shutdown(Shutdown::Write);
+                break closed;
+            },
             Ok(n) => {
-                if let Err(e) = client_stream.write_all(&buffer[0..n]) {
-                    println!("Error writing to client: {}", e);
-                    break;
+                if first_byte.is_none() {
+                    first_byte = Some(start.elapsed());
                 }
+                if let Err(e) = write_all_nonblocking(&mut to, &buffer[0..n]) {
+                    println!("Error writing tunnel data: {}", e);
+                    break match e.kind() {
+                        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset => write_reset,
+                        _ => CloseReason::Error(e.to_string()),
+                    };
+                }
+                bytes += n as u64;
             },
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 thread::sleep(Duration::from_millis(10)); // Wait and retry
                 continue;
             },
-            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
-                println!("Client disconnected, stopping write.");
-                break;
-            },
-            Err(ref e) if e.kind() == ErrorKind::ConnectionReset => {
-                println!("Client closed connection abruptly.");
-                break;
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe || e.kind() == ErrorKind::ConnectionReset => {
+                println!("Tunnel connection closed abruptly.");
+                break reset;
             },
             Err(e) => {
-                println!("Error reading from server: {}", e);
-                break;
+                println!("Error reading tunnel data: {}", e);
+                break CloseReason::Error(e.to_string());
             }
         }
+    };
+
+    // Unblock the opposite direction on errors
+    if !matches!(reason, CloseReason::ClientClosed | CloseReason::ServerClosed) {
+        let _ = from.shutdown(Shutdown::Both);
+        let _ = to.shutdown(Shutdown::Both);
     }
 
-    // Wait for the client-to-server thread to finish
-    let _ = client_to_server.join();
+    (bytes, first_byte, reason)
+}
 
+/// `write_all` for a non-blocking socket
+fn write_all_nonblocking(stream: &mut TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero)),
+            Ok(n) => data = &data[n..],
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e),
+        }
+    }
     Ok(())
 }
+
+/// Pipes data in both directions until the tunnel closes
+///
+/// - returns bytes up, bytes down, time to first byte from the server and the close reason
+fn tunnel_data(client_stream: TcpStream, server_stream: TcpStream, start: Instant) -> io::Result<(u64, u64, Option<Duration>, CloseReason)> {
+    // Clone the streams for the two threads
+    let client_read = client_stream.try_clone()?;
+    let server_read = server_stream.try_clone()?;
+
+    // Create a thread to handle client -> server data
+    let client_to_server = thread::spawn(move || {
+        let result = copy_until_closed(client_read, server_stream, Side::Client, start);
+        (result, Instant::now())
+    });
+
+    // Handle server -> client data in the current thread
+    let (bytes_down, time_to_first_byte, server_reason) = copy_until_closed(server_read, client_stream, Side::Server, start);
+    let server_done = Instant::now();
+
+    // Wait for the client-to-server thread to finish
+    let ((bytes_up, _, client_reason), client_done) = match client_to_server.join() {
+        Ok(result) => result,
+        Err(_) => ((0, None, CloseReason::Error("tunnel thread panicked".to_string())), Instant::now()),
+    };
+
+    // Whichever direction finished first decided how the tunnel closed
+    let close_reason = if client_done <= server_done { client_reason } else { server_reason };
+
+    Ok((bytes_up, bytes_down, time_to_first_byte, close_reason))
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::utils::parsing::url_authority;
+
+// Targeted invalidation of cached responses:
+// a purge removes one URL, a ban removes every URL matching a BanRule
+
+/// Which cached URLs a ban removes
+#[derive(Clone, Debug)]
+pub enum BanRule {
+    /// Every URL on the host, with or without a port
+    Host(String),
+
+    /// Every URL starting with the prefix
+    Prefix(String),
+
+    /// Every URL the expression matches anywhere
+    Regex(Regex),
+}
+
+impl BanRule {
+    /// Builds a rule from its kind (host, prefix or regex) and value
+    pub fn parse(kind: &str, value: &str) -> Result<BanRule, String> {
+        match kind.to_ascii_lowercase().as_str() {
+            "host" => Ok(BanRule::Host(value.to_ascii_lowercase())),
+            "prefix" => Ok(BanRule::Prefix(value.to_string())),
+            "regex" => Regex::new(value)
+                .map(BanRule::Regex)
+                .map_err(|e| format!("Invalid ban regex: {}", e)),
+            _ => Err(format!("Unknown ban kind: {} (use host, prefix or regex)", kind)),
+        }
+    }
+
+    /// Checks if the cached response for a URL is banned
+    pub fn matches(&self, url: &str) -> bool {
+        match self {
+            BanRule::Host(host) => {
+                let authority = url_authority(url).to_ascii_lowercase();
+                authority == *host || strip_port(&authority) == host.as_str()
+            },
+            BanRule::Prefix(prefix) => url.starts_with(prefix.as_str()),
+            BanRule::Regex(regex) => regex.is_match(url),
+        }
+    }
+}
+
+impl fmt::Display for BanRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanRule::Host(host) => write!(f, "host {}", host),
+            BanRule::Prefix(prefix) => write!(f, "prefix {}", prefix),
+            BanRule::Regex(regex) => write!(f, "regex {}", regex),
+        }
+    }
+}
+
+/// Host without its port, IPv6 literals keep their brackets
+fn strip_port(authority: &str) -> &str {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::proxy::cache::CacheEntry;
+use crate::proxy::eviction::{EvictionPolicy, EvictionTracker};
+use crate::proxy::store::EvictionListener;
+
+// Entry map of MemoryStore (the default L1 of HttpCache), bounded in bytes by the configured eviction policy
+
+/// Estimated fixed cost of an entry besides its key, body and headers (map slot, metadata, bookkeeping)
+pub const ENTRY_OVERHEAD: usize = 256;
+
+/// Bytes an entry is charged against the L1 capacity
+pub fn entry_size(key: &str, entry: &CacheEntry) -> usize {
+    let headers: usize = entry.headers.iter().map(|(name, value)| name.len() + value.len()).sum();
+    key.len() + entry.response_data.len() + headers + ENTRY_OVERHEAD
+}
+
+/// An entry held in L1 together with its hit count
+#[derive(Clone, Debug)]
+pub struct L1Entry {
+    pub entry: CacheEntry,
+    pub hits: usize,
+
+    /// When the entry is dropped (UNIX seconds), None if only eviction removes it
+    pub retain_until: Option<u64>,
+}
+
+impl L1Entry {
+    /// Checks if the entry is past its retention time
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.retain_until.map(|retain_until| retain_until <= now).unwrap_or(false)
+    }
+}
+
+/// Eviction counters of the L1 cache
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EvictionStats {
+    /// Entries dropped to make room for others
+    pub evictions: u64,
+
+    /// Entries the admission policy refused to store
+    pub rejections: u64,
+
+    /// Entries removed because they expired
+    pub expirations: u64,
+}
+
+///
+/// Map of L1 entries plus the eviction bookkeeping that keeps it within capacity
+///
+pub struct L1Cache {
+    entries: HashMap<String, L1Entry>,
+    tracker: EvictionTracker,
+    stats: EvictionStats,
+    listener: Option<EvictionListener>,
+}
+
+impl L1Cache {
+    /// Creates an empty L1 cache holding at most `capacity` bytes
+    pub fn new(policy: EvictionPolicy, capacity: usize) -> Self {
+        L1Cache {
+            entries: HashMap::new(),
+            tracker: EvictionTracker::new(policy, capacity),
+            stats: EvictionStats::default(),
+            listener: None,
+        }
+    }
+
+    /// Sets the listener told about every entry evicted to make room
+    pub fn set_eviction_listener(&mut self, listener: EvictionListener) {
+        self.listener = Some(listener);
+    }
+
+    /// Looks up an entry and records the hit
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut L1Entry> {
+        let l1_entry = self.entries.get_mut(key)?;
+        l1_entry.hits += 1;
+        self.tracker.record_access(key);
+        Some(l1_entry)
+    }
+
+    /// Looks up an entry without counting it as a hit
+    pub fn peek(&self, key: &str) -> Option<&L1Entry> {
+        self.entries.get(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Stores an entry, evicting others until its size fits
+    ///
+    /// - returns false if the eviction policy did not admit the entry (or it is larger than L1)
+    pub fn insert(&mut self, key: &str, entry: CacheEntry, retain_until: Option<u64>) -> bool {
+        let hits = self.entries.get(key).map(|l1_entry| l1_entry.hits).unwrap_or(0);
+        let mut admitted = true;
+
+        for evicted in self.tracker.insert(key, entry_size(key, &entry)) {
+            self.entries.remove(&evicted);
+            if evicted == key {
+                admitted = false;
+                self.stats.rejections += 1;
+            } else {
+                self.stats.evictions += 1;
+                if let Some(listener) = &self.listener {
+                    listener(&evicted);
+                }
+            }
+        }
+
+        if admitted {
+            self.entries.insert(key.to_string(), L1Entry { entry, hits, retain_until });
+        }
+        admitted
+    }
+
+    /// Removes an entry
+    pub fn remove(&mut self, key: &str) -> Option<L1Entry> {
+        self.tracker.remove(key);
+        self.entries.remove(key)
+    }
+
+    /// Removes an entry because it expired
+    pub fn expire(&mut self, key: &str) {
+        if self.remove(key).is_some() {
+            self.stats.expirations += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.tracker.clear();
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Entries without counting hits
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &L1Entry)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Bytes currently charged against the capacity
+    pub fn total_bytes(&self) -> usize {
+        self.tracker.total_weight()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> EvictionStats {
+        self.stats
+    }
+}
@@ -5,10 +5,12 @@ use std::sync::Arc;
 use super::handler::handle_client_connection;
 use crate::utils::host_filtering::Blacklist;
 use crate::proxy::cache::HttpCache;
+use crate::utils::stats::ProxyStats;
+use crate::proxy::config::ProxyConfig;
 
 /// # Listens for incoming connections
-
-pub fn start_proxy(port: u16, blacklist: Arc<Blacklist>, cache: Arc<HttpCache>) {
+pub fn start_proxy(blacklist: Arc<Blacklist>, cache: Arc<HttpCache>, stats: Arc<ProxyStats>, config: Arc<ProxyConfig>) {
+    let port = config.port;
     let listener = TcpListener::bind(("0.0.0.0", port)).expect("Failed to bind to port");
     println!("Listening on port {}...", port);
 
@@ -20,8 +22,10 @@ pub fn start_proxy(port: u16, blacklist: Arc<Blacklist>, cache: Arc<HttpCache>)
                 // Arc Clone blacklist and L1 Cache hashmap to use in thread
                 let cache_clone = Arc::clone(&cache);
                 let blacklist_clone = Arc::clone(&blacklist);
+                let stats_clone = Arc::clone(&stats);
+                let config_clone = Arc::clone(&config);
 
-                thread::spawn(move || handle_client_connection(stream, blacklist_clone, cache_clone));
+                thread::spawn(move || handle_client_connection(stream, blacklist_clone, cache_clone, stats_clone, config_clone));
                 connection_counter += 1;
             }
             Err(e) => {
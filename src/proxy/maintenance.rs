@@ -0,0 +1,266 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::proxy::cache::now_secs;
+use crate::proxy::l1::L1Cache;
+
+// Background threads of the cache:
+// ExpirySweeper - one thread removing expired MemoryStore entries in deadline order (min-heap),
+// paused in offline mode
+// BackgroundWorker - one thread running queued jobs (L2 -> L1 promotions) from a bounded queue
+
+/// Deadlines waiting to be swept
+struct SweepQueue {
+    /// (deadline, key) ordered by the earliest deadline
+    deadlines: BinaryHeap<Reverse<(u64, String)>>,
+
+    /// Latest deadline per key, older heap items of a key are skipped
+    scheduled: HashMap<String, u64>,
+
+    /// Nothing is swept while set, overdue keys are swept once it is cleared
+    paused: bool,
+
+    shutdown: bool,
+}
+
+struct SweeperShared {
+    queue: Mutex<SweepQueue>,
+    wakeup: Condvar,
+}
+
+///
+/// Single thread that expires memory store entries once they are past their retention time
+///
+pub struct ExpirySweeper {
+    shared: Arc<SweeperShared>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ExpirySweeper {
+    /// Starts the sweeper thread for the given L1 cache
+    pub fn start(l1_entries: Arc<RwLock<L1Cache>>) -> Self {
+        let shared = Arc::new(SweeperShared {
+            queue: Mutex::new(SweepQueue {
+                deadlines: BinaryHeap::new(),
+                scheduled: HashMap::new(),
+                paused: false,
+                shutdown: false,
+            }),
+            wakeup: Condvar::new(),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        let handle = thread::spawn(move || sweep(thread_shared, l1_entries));
+
+        ExpirySweeper {
+            shared,
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Schedules a key to be checked at `deadline` (UNIX seconds), replacing an earlier schedule
+    pub fn schedule(&self, key: &str, deadline: u64) {
+        let mut queue = lock(&self.shared.queue);
+        if queue.shutdown {
+            return;
+        }
+
+        let wake = queue
+            .deadlines
+            .peek()
+            .map(|Reverse((earliest, _))| deadline < *earliest)
+            .unwrap_or(true);
+        queue.scheduled.insert(key.to_string(), deadline);
+        queue.deadlines.push(Reverse((deadline, key.to_string())));
+
+        // The thread only has to wake up early if this is the new earliest deadline
+        if wake {
+            self.shared.wakeup.notify_one();
+        }
+    }
+
+    /// Pauses or resumes sweeping, keys keep being scheduled meanwhile
+    pub fn set_paused(&self, paused: bool) {
+        lock(&self.shared.queue).paused = paused;
+        self.shared.wakeup.notify_one();
+    }
+
+    /// Stops the sweeper thread and waits for it to exit
+    pub fn shutdown(&self) {
+        {
+            let mut queue = lock(&self.shared.queue);
+            queue.shutdown = true;
+            queue.deadlines.clear();
+            queue.scheduled.clear();
+        }
+        self.shared.wakeup.notify_one();
+
+        if let Some(handle) = lock(&self.handle).take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Sweeper loop: sleeps until the earliest deadline, then expires the keys that are due
+fn sweep(shared: Arc<SweeperShared>, l1_entries: Arc<RwLock<L1Cache>>) {
+    let mut queue = lock(&shared.queue);
+
+    loop {
+        if queue.shutdown {
+            return;
+        }
+
+        let now = now_secs();
+        let next = queue.deadlines.peek().map(|Reverse((deadline, _))| *deadline);
+        match next {
+            _ if queue.paused => {
+                queue = match shared.wakeup.wait(queue) {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+            },
+            None => {
+                queue = match shared.wakeup.wait(queue) {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+            },
+            Some(deadline) if deadline > now => {
+                let timeout = Duration::from_secs(deadline - now);
+                queue = match shared.wakeup.wait_timeout(queue, timeout) {
+                    Ok((guard, _)) => guard,
+                    Err(poisoned) => poisoned.into_inner().0,
+                };
+            },
+            Some(_) => {
+                let mut due = Vec::new();
+                while let Some(Reverse((deadline, _))) = queue.deadlines.peek() {
+                    if *deadline > now {
+                        break;
+                    }
+                    let Reverse((deadline, key)) = match queue.deadlines.pop() {
+                        Some(item) => item,
+                        None => break,
+                    };
+                    // Skip items superseded by a later schedule of the same key
+                    if queue.scheduled.get(&key) == Some(&deadline) {
+                        queue.scheduled.remove(&key);
+                        due.push(key);
+                    }
+                }
+
+                // Don't hold the queue while waiting for the L1 lock
+                drop(queue);
+                expire_due(&l1_entries, &due);
+                queue = lock(&shared.queue);
+            }
+        }
+    }
+}
+
+/// Expires the given keys unless they were replaced by a fresher entry meanwhile
+fn expire_due(l1_entries: &RwLock<L1Cache>, keys: &[String]) {
+    if keys.is_empty() {
+        return;
+    }
+
+    let mut l1_entries = match l1_entries.write() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let now = now_secs();
+    for key in keys {
+        let expired = l1_entries
+            .peek(key)
+            .map(|l1_entry| l1_entry.is_expired(now))
+            .unwrap_or(false);
+        if expired {
+            l1_entries.expire(key);
+        }
+    }
+}
+
+/// A job for the background worker
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+///
+/// Single worker thread fed by a bounded queue, jobs are dropped when the queue is full
+///
+pub struct BackgroundWorker {
+    name: &'static str,
+
+    /// None is the shutdown signal
+    sender: SyncSender<Option<Job>>,
+
+    stopping: Arc<AtomicBool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl BackgroundWorker {
+    /// Starts a worker thread accepting up to `capacity` queued jobs
+    pub fn start(name: &'static str, capacity: usize) -> Self {
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let thread_stopping = Arc::clone(&stopping);
+        let handle = thread::spawn(move || work(receiver, thread_stopping));
+
+        BackgroundWorker {
+            name,
+            sender,
+            stopping,
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Queues a job without blocking
+    ///
+    /// - returns false if the queue is full or the worker was shut down
+    pub fn submit(&self, job: Job) -> bool {
+        if self.stopping.load(Ordering::Acquire) {
+            return false;
+        }
+        match self.sender.try_send(Some(job)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                println!("{} queue is full, dropping job", self.name);
+                false
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Stops the worker, skipping jobs still queued, and waits for it to exit
+    pub fn shutdown(&self) {
+        if self.stopping.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // Queued jobs are skipped, so there is room for the signal soon
+        let _ = self.sender.send(None);
+
+        if let Some(handle) = lock(&self.handle).take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Worker loop: runs jobs until the shutdown signal
+fn work(receiver: Receiver<Option<Job>>, stopping: Arc<AtomicBool>) {
+    while let Ok(Some(job)) = receiver.recv() {
+        if !stopping.load(Ordering::Acquire) {
+            job();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
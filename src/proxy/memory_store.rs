@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::proxy::cache::{now_secs, CacheEntry};
+use crate::proxy::eviction::EvictionPolicy;
+use crate::proxy::l1::{L1Cache, L1Entry};
+use crate::proxy::maintenance::ExpirySweeper;
+use crate::proxy::store::{CacheError, CacheStore, EvictionListener, StoreStats, StoredEntry};
+
+///
+/// In-memory store bounded in bytes by an eviction policy, expired entries are removed by a sweeper thread
+///
+pub struct MemoryStore {
+    entries: Arc<RwLock<L1Cache>>,
+    sweeper: ExpirySweeper,
+
+    /// Expired entries are kept and served while set
+    expiry_paused: AtomicBool,
+}
+
+impl MemoryStore {
+    /// Creates an empty store holding at most `capacity` bytes
+    pub fn new(policy: EvictionPolicy, capacity: usize) -> Self {
+        let entries = Arc::new(RwLock::new(L1Cache::new(policy, capacity)));
+        MemoryStore {
+            sweeper: ExpirySweeper::start(entries.clone()),
+            entries,
+            expiry_paused: AtomicBool::new(false),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, L1Cache> {
+        match self.entries.read() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, L1Cache> {
+        match self.entries.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Metadata of an L1 entry without cloning its body
+fn stored_entry(key: &str, l1_entry: &L1Entry) -> StoredEntry {
+    StoredEntry {
+        key: key.to_string(),
+        entry: l1_entry.entry.without_body(),
+        size: l1_entry.entry.response_data.len(),
+        hits: Some(l1_entry.hits),
+        retain_until: l1_entry.retain_until,
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<CacheEntry>, CacheError> {
+        let paused = self.expiry_paused.load(Ordering::Relaxed);
+        let mut entries = self.write();
+        let expired = match entries.get_mut(key) {
+            Some(l1_entry) if paused || !l1_entry.is_expired(now_secs()) => return Ok(Some(l1_entry.entry.clone())),
+            Some(_) => true,
+            None => false,
+        };
+        // Past its stale window, the sweeper just hasn't got to it yet
+        if expired {
+            entries.expire(key);
+        }
+        Ok(None)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, CacheError> {
+        Ok(self.read().contains_key(key))
+    }
+
+    fn put(&self, key: &str, entry: &CacheEntry, retain_until: Option<u64>) -> Result<(), CacheError> {
+        if !self.write().insert(key, entry.clone(), retain_until) {
+            println!("Memory store admission policy rejected {}", key);
+            return Ok(());
+        }
+
+        // Schedule removal after TTL and stale window
+        if let Some(retain_until) = retain_until {
+            self.sweeper.schedule(key, retain_until);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        Ok(self.write().remove(key).is_some())
+    }
+
+    fn clear(&self) -> Result<(), CacheError> {
+        self.write().clear();
+        Ok(())
+    }
+
+    fn scan(&self) -> Result<Vec<String>, CacheError> {
+        Ok(self.read().keys())
+    }
+
+    fn peek(&self, key: &str) -> Result<Option<StoredEntry>, CacheError> {
+        Ok(self.read().peek(key).map(|l1_entry| stored_entry(key, l1_entry)))
+    }
+
+    fn export(&self, key: &str) -> Result<Option<(CacheEntry, Option<u64>)>, CacheError> {
+        Ok(self.read().peek(key).map(|l1_entry| (l1_entry.entry.clone(), l1_entry.retain_until)))
+    }
+
+    fn entries(&self) -> Result<Vec<StoredEntry>, CacheError> {
+        let entries = self.read();
+        Ok(entries.iter().map(|(key, l1_entry)| stored_entry(key, l1_entry)).collect())
+    }
+
+    fn set_eviction_listener(&self, listener: EvictionListener) {
+        self.write().set_eviction_listener(listener);
+    }
+
+    fn set_expiry_paused(&self, paused: bool) {
+        self.expiry_paused.store(paused, Ordering::Relaxed);
+        self.sweeper.set_paused(paused);
+    }
+
+    fn stats(&self) -> Result<StoreStats, CacheError> {
+        let entries = self.read();
+        Ok(StoreStats {
+            entries: entries.len(),
+            bytes: Some(entries.total_bytes()),
+            evictions: entries.stats(),
+        })
+    }
+
+    fn shutdown(&self) {
+        self.sweeper.shutdown();
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::proxy::cache::HttpCache;
+use crate::proxy::config::ProxyConfig;
+use crate::proxy::invalidation::BanRule;
+use crate::utils::parsing::{get_header, request_url, HttpRequest};
+use crate::utils::responses::{ok_response, ErrorResponse, Status};
+
+/// Checks if roxy answers the request method itself instead of forwarding it
+pub fn is_invalidation_method(method: &str) -> bool {
+    method.eq_ignore_ascii_case("PURGE") || method.eq_ignore_ascii_case("BAN")
+}
+
+/// # Handles PURGE and BAN requests from allowed clients
+///
+/// * `PURGE <url>` removes the cached response for the URL
+/// * `BAN <url>` removes every cached URL starting with it, or the ones matching
+///   the `X-Ban-Host` or `X-Ban-Regex` header when present
+pub fn handle_invalidation(request: &HttpRequest, host: &str, client_stream: &mut TcpStream, cache: &HttpCache, config: &ProxyConfig) -> Result<(), ErrorResponse> {
+    let allowed = client_stream
+        .peer_addr()
+        .map(|addr| config.purge_allowed.contains(&addr.ip().to_canonical()))
+        .unwrap_or(false);
+    if !allowed {
+        return Err(ErrorResponse::new(Status::Forbidden).with_message(format!("{} is not allowed from this address", request.method)));
+    }
+
+    let url = request_url(&request.path, host);
+    let body = if request.method.eq_ignore_ascii_case("PURGE") {
+        match cache.invalidate(&url) {
+            Ok(true) => format!("Purged {}\n", url),
+            Ok(false) => return Err(ErrorResponse::new(Status::NotFound).with_message(format!("{} is not in the cache", url))),
+            Err(e) => return Err(ErrorResponse::new(Status::ServiceUnavailable).with_message(format!("Failed to purge {}: {}", url, e))),
+        }
+    } else {
+        let rule = match (get_header(&request.headers, "x-ban-host"), get_header(&request.headers, "x-ban-regex")) {
+            (Some(host), _) => BanRule::parse("host", host.trim()),
+            (None, Some(regex)) => BanRule::parse("regex", regex.trim()),
+            (None, None) => BanRule::parse("prefix", &url),
+        }
+        .map_err(|e| ErrorResponse::new(Status::BadRequest).with_message(e))?;
+
+        match cache.ban(&rule) {
+            Ok(count) => format!("Banned {}: {} entries removed\n", rule, count),
+            Err(e) => return Err(ErrorResponse::new(Status::ServiceUnavailable).with_message(format!("Failed to ban {}: {}", rule, e))),
+        }
+    };
+
+    println!("{}", body.trim_end());
+    if let Err(e) = client_stream.write_all(&ok_response(&body)) {
+        println!("Failed to send {} response: {}", request.method, e);
+    }
+    Ok(())
+}
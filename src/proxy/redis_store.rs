@@ -0,0 +1,478 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use redis::{Commands, Connection, IntoConnectionInfo, RedisResult};
+use crate::proxy::cache::{now_secs, CacheEntry};
+use crate::proxy::codec::{decode_entry, encode_meta};
+use crate::proxy::store::{CacheError, CacheStore, StoreStats, StoredEntry};
+
+// Redis (L2) store:
+// RedisConfig - server, credentials and connection pool settings (part of CacheConfig)
+// ConnectionPool - reuses connections instead of opening one per command
+// RedisStore - entries as hashes of binary metadata (see codec.rs) and the raw body
+// All keys live under `<key_prefix>:v<KEY_VERSION>:`, so roxy can share a Redis database
+
+/// Version of the stored format, bumping it makes roxy ignore (and `clear` remove) older entries
+const KEY_VERSION: u32 = 2;
+
+/// Hash fields of an entry
+const META_FIELD: &str = "meta";
+const BODY_FIELD: &str = "body";
+
+/// Version 1 stored JSON strings (with the body as an array of numbers), still read and
+/// migrated to the current format when hit
+const LEGACY_KEY_VERSION: u32 = 1;
+
+/// Suffix of the separate metadata key of version 1 entries
+const LEGACY_META_SUFFIX: &str = "#meta";
+
+/// Reply of `HMGET <key> meta body`
+type EntryFields = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Keys removed per UNLINK when clearing
+const UNLINK_BATCH: usize = 500;
+
+///
+/// Settings of the Redis connection
+///
+#[derive(Clone, Debug)]
+pub struct RedisConfig {
+    /// Server URL, `redis://` or `rediss://` (TLS), may carry credentials and a database
+    pub url: String,
+
+    /// Username for AUTH, overrides the URL
+    pub username: Option<String>,
+
+    /// Password for AUTH, overrides the URL
+    pub password: Option<String>,
+
+    /// Database number, overrides the URL
+    pub db: Option<i64>,
+
+    /// Namespace of roxy's keys, followed by the format version (`roxy` gives `roxy:v2:<key>`)
+    pub key_prefix: String,
+
+    /// Maximum number of open connections
+    pub pool_size: usize,
+
+    /// Connecting (TCP, TLS and AUTH) has to finish within this
+    pub connect_timeout: Duration,
+
+    /// Read/write timeout of a command
+    pub command_timeout: Duration,
+
+    /// How long a caller waits for a free connection when all are in use
+    pub checkout_timeout: Duration,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: "redis://127.0.0.1/".to_string(),
+            username: None,
+            password: None,
+            db: None,
+            key_prefix: "roxy".to_string(),
+            pool_size: 8,
+            connect_timeout: Duration::from_secs(2),
+            command_timeout: Duration::from_secs(2),
+            checkout_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+struct PoolState {
+    idle: Vec<Connection>,
+
+    /// Idle plus checked out connections
+    open: usize,
+}
+
+///
+/// Bounded pool of blocking Redis connections
+///
+pub struct ConnectionPool {
+    client: redis::Client,
+    config: RedisConfig,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+/// A connection checked out of the pool, returned to it when dropped
+pub struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    connection: Option<Connection>,
+}
+
+impl ConnectionPool {
+    /// Creates the pool (no connection is opened yet)
+    pub fn new(config: &RedisConfig) -> Result<Self, CacheError> {
+        let mut info = config.url.as_str().into_connection_info()?;
+        if let Some(username) = &config.username {
+            info.redis.username = Some(username.clone());
+        }
+        if let Some(password) = &config.password {
+            info.redis.password = Some(password.clone());
+        }
+        if let Some(db) = config.db {
+            info.redis.db = db;
+        }
+
+        Ok(ConnectionPool {
+            client: redis::Client::open(info)?,
+            config: config.clone(),
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            returned: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Takes an idle connection, opens a new one below the pool size, or waits for one
+    pub fn get(&self) -> Result<PooledConnection<'_>, CacheError> {
+        let deadline = Instant::now() + self.config.checkout_timeout;
+        let mut state = self.lock();
+
+        loop {
+            if let Some(connection) = state.idle.pop() {
+                return Ok(PooledConnection {
+                    pool: self,
+                    connection: Some(connection),
+                });
+            }
+
+            if state.open < self.config.pool_size.max(1) {
+                state.open += 1;
+                drop(state);
+                return match self.connect() {
+                    Ok(connection) => Ok(PooledConnection {
+                        pool: self,
+                        connection: Some(connection),
+                    }),
+                    Err(e) => {
+                        self.release_slot();
+                        Err(e.into())
+                    }
+                };
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(CacheError::Busy(format!(
+                    "all {} Redis connections in use",
+                    self.config.pool_size
+                )));
+            }
+            state = match self.returned.wait_timeout(state, deadline - now) {
+                Ok((guard, _)) => guard,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    fn connect(&self) -> RedisResult<Connection> {
+        let connection = self.client.get_connection_with_timeout(self.config.connect_timeout)?;
+        connection.set_read_timeout(Some(self.config.command_timeout))?;
+        connection.set_write_timeout(Some(self.config.command_timeout))?;
+        Ok(connection)
+    }
+
+    /// Forgets a connection that was closed instead of returned
+    fn release_slot(&self) {
+        self.lock().open -= 1;
+        self.returned.notify_one();
+    }
+}
+
+impl PooledConnection<'_> {
+    /// Closes the connection instead of returning it, after it failed
+    fn discard(mut self) {
+        self.connection = None;
+        self.pool.release_slot();
+    }
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().expect("connection taken")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().expect("connection taken")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.lock().idle.push(connection);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+///
+/// Store backed by a Redis server, entries are hashes expiring with Redis TTLs
+///
+pub struct RedisStore {
+    pool: ConnectionPool,
+
+    /// `<key_prefix>:` - every version of roxy's keys
+    prefix: String,
+
+    /// `<key_prefix>:v<KEY_VERSION>:` - keys of the current format
+    namespace: String,
+
+    /// `<key_prefix>:v<LEGACY_KEY_VERSION>:` - JSON entries still read during migration
+    legacy_namespace: String,
+}
+
+impl RedisStore {
+    /// Connects to Redis and checks that it answers
+    pub fn open(config: &RedisConfig) -> Result<Self, CacheError> {
+        let prefix = format!("{}:", config.key_prefix);
+        let store = RedisStore {
+            pool: ConnectionPool::new(config)?,
+            namespace: format!("{}v{}:", prefix, KEY_VERSION),
+            legacy_namespace: format!("{}v{}:", prefix, LEGACY_KEY_VERSION),
+            prefix,
+        };
+
+        // Test connection to Redis
+        store.with_connection(|con| con.ping::<String>())?;
+        Ok(store)
+    }
+
+    /// Runs commands on a pooled connection, closing the connection if it broke
+    fn with_connection<T>(&self, op: impl FnOnce(&mut Connection) -> RedisResult<T>) -> Result<T, CacheError> {
+        let mut con = self.pool.get()?;
+        match op(&mut con) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                if e.is_unrecoverable_error() || e.is_timeout() {
+                    con.discard();
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Redis key of an entry
+    fn entry_key(&self, key: &str) -> String {
+        format!("{}{}", self.namespace, key)
+    }
+
+    /// Redis keys of an entry in the legacy format (JSON string plus metadata key)
+    fn legacy_keys(&self, key: &str) -> [String; 2] {
+        [
+            format!("{}{}", self.legacy_namespace, key),
+            format!("{}{}{}", self.legacy_namespace, key, LEGACY_META_SUFFIX),
+        ]
+    }
+
+    /// Redis keys matching a glob pattern, iterated with SCAN
+    fn scan_keys(&self, pattern: &str) -> Result<Vec<String>, CacheError> {
+        self.with_connection(|con| Ok(con.scan_match::<_, String>(pattern)?.collect()))
+    }
+
+    /// Rewrites a legacy JSON entry in the current format, keeping its remaining TTL
+    fn migrate(&self, key: &str, entry: &CacheEntry, ttl: i64) {
+        let retain_until = if ttl > 0 { Some(now_secs() + ttl as u64) } else { None };
+        let result = self
+            .put(key, entry, retain_until)
+            .and_then(|_| self.with_connection(|con| con.del::<_, ()>(&self.legacy_keys(key))));
+        if let Err(e) = result {
+            println!("Failed to migrate legacy cache entry {}: {}", key, e);
+        }
+    }
+}
+
+impl CacheStore for RedisStore {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<CacheEntry>, CacheError> {
+        let [legacy_key, _] = self.legacy_keys(key);
+
+        // Current and legacy format in one round trip
+        let ((meta, body), legacy, legacy_ttl): (EntryFields, Option<String>, i64) =
+            self.with_connection(|con| {
+                redis::pipe()
+                    .hget(self.entry_key(key), &[META_FIELD, BODY_FIELD])
+                    .get(&legacy_key)
+                    .ttl(&legacy_key)
+                    .query(con)
+            })?;
+
+        if let (Some(meta), Some(body)) = (meta, body) {
+            return match decode_entry(&meta, body) {
+                Ok(entry) => Ok(Some(entry)),
+                Err(e) => {
+                    println!("Error decoding cache entry: {}", e);
+                    Ok(None)
+                }
+            };
+        }
+
+        match legacy.map(|serialized_entry| serde_json::from_str::<CacheEntry>(&serialized_entry)) {
+            Some(Ok(entry)) => {
+                self.migrate(key, &entry, legacy_ttl);
+                Ok(Some(entry))
+            },
+            Some(Err(e)) => {
+                println!("Error deserializing cache entry: {}", e);
+                Ok(None)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, CacheError> {
+        let [legacy_key, _] = self.legacy_keys(key);
+        let found: usize = self.with_connection(|con| con.exists(&[self.entry_key(key), legacy_key]))?;
+        Ok(found > 0)
+    }
+
+    fn put(&self, key: &str, entry: &CacheEntry, retain_until: Option<u64>) -> Result<(), CacheError> {
+        let entry_key = self.entry_key(key);
+
+        // Metadata and body as fields of one hash, written with its TTL in one round trip
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.hset_multiple(
+            &entry_key,
+            &[(META_FIELD, encode_meta(entry)), (BODY_FIELD, entry.response_data.clone())],
+        )
+        .ignore();
+        match retain_until {
+            Some(retain_until) => {
+                let now = now_secs();
+                // Don't cache if already expired
+                if retain_until <= now {
+                    return Ok(());
+                }
+                pipe.expire(&entry_key, (retain_until - now) as i64).ignore();
+            },
+            None => {
+                pipe.persist(&entry_key).ignore();
+            }
+        }
+        self.with_connection(|con| pipe.query::<()>(con))
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        let [legacy_key, legacy_meta_key] = self.legacy_keys(key);
+        let removed: usize = self.with_connection(|con| con.del(&[self.entry_key(key), legacy_key, legacy_meta_key]))?;
+        Ok(removed > 0)
+    }
+
+    /// Removes roxy's keys of every format version, other data in the database is left alone
+    fn clear(&self) -> Result<(), CacheError> {
+        let keys = self.scan_keys(&format!("{}*", escape_glob(&self.prefix)))?;
+        for batch in keys.chunks(UNLINK_BATCH) {
+            self.with_connection(|con| con.unlink::<_, ()>(batch))?;
+        }
+        Ok(())
+    }
+
+    fn scan(&self) -> Result<Vec<String>, CacheError> {
+        let mut keys: Vec<String> = self
+            .scan_keys(&format!("{}*", escape_glob(&self.namespace)))?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.namespace).map(|key| key.to_string()))
+            .collect();
+
+        // Legacy entries not migrated yet
+        let legacy = self.scan_keys(&format!("{}*", escape_glob(&self.legacy_namespace)))?;
+        keys.extend(
+            legacy
+                .into_iter()
+                .filter(|key| !key.ends_with(LEGACY_META_SUFFIX))
+                .filter_map(|key| key.strip_prefix(&self.legacy_namespace).map(|key| key.to_string())),
+        );
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    fn peek(&self, key: &str) -> Result<Option<StoredEntry>, CacheError> {
+        let entry_key = self.entry_key(key);
+
+        // Metadata, body length and TTL without transferring the body
+        let (meta, size, ttl): (Option<Vec<u8>>, usize, i64) = self.with_connection(|con| {
+            redis::pipe()
+                .hget(&entry_key, META_FIELD)
+                .cmd("HSTRLEN").arg(&entry_key).arg(BODY_FIELD)
+                .ttl(&entry_key)
+                .query(con)
+        })?;
+
+        let meta = match meta {
+            Some(meta) => meta,
+            // Legacy entries are migrated by reading them
+            None => return Ok(self.get(key)?.map(|entry| StoredEntry::from_entry(key, entry))),
+        };
+        let mut stored = StoredEntry::from_entry(key, decode_entry(&meta, Vec::new())?);
+        stored.size = size;
+        stored.retain_until = (ttl > 0).then(|| now_secs() + ttl as u64);
+        Ok(Some(stored))
+    }
+
+    fn export(&self, key: &str) -> Result<Option<(CacheEntry, Option<u64>)>, CacheError> {
+        let entry_key = self.entry_key(key);
+        let [legacy_key, _] = self.legacy_keys(key);
+
+        // Both formats with their TTLs in one round trip, legacy entries are left for get to migrate
+        let ((meta, body), ttl, legacy, legacy_ttl): (EntryFields, i64, Option<String>, i64) =
+            self.with_connection(|con| {
+                redis::pipe()
+                    .hget(&entry_key, &[META_FIELD, BODY_FIELD])
+                    .ttl(&entry_key)
+                    .get(&legacy_key)
+                    .ttl(&legacy_key)
+                    .query(con)
+            })?;
+
+        let retain_until = |ttl: i64| (ttl > 0).then(|| now_secs() + ttl as u64);
+        if let (Some(meta), Some(body)) = (meta, body) {
+            return Ok(Some((decode_entry(&meta, body)?, retain_until(ttl))));
+        }
+        match legacy {
+            Some(serialized_entry) => Ok(Some((serde_json::from_str::<CacheEntry>(&serialized_entry)?, retain_until(legacy_ttl)))),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> Result<StoreStats, CacheError> {
+        Ok(StoreStats {
+            entries: self.scan()?.len(),
+            ..StoreStats::default()
+        })
+    }
+}
+
+/// Escapes the glob characters of a literal for SCAN MATCH
+fn escape_glob(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,233 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::proxy::cache::{CacheConfig, CacheEntry};
+use crate::proxy::store::{CacheError, CacheStore, EvictionListener, StoreBackend, StoreHealth, StoreStats, StoredEntry};
+
+// Wraps the L2 store so an unreachable backend doesn't stop roxy:
+// the store is opened lazily, marked unhealthy when it fails, and only
+// tried again once its backoff has passed; in between calls fail fast
+
+struct HealthState {
+    /// None until the backend could be opened
+    store: Option<Arc<dyn CacheStore>>,
+
+    /// Failures in a row, 0 while healthy
+    failures: u32,
+
+    /// When the current outage started
+    down_since: Option<Instant>,
+
+    /// No calls reach the backend before this
+    retry_at: Instant,
+
+    last_error: Option<String>,
+
+    /// Registered on the backend once it is opened
+    eviction_listener: Option<EvictionListener>,
+    expiry_paused: bool,
+}
+
+///
+/// Store that tracks the health of another store and reconnects to it with backoff
+///
+pub struct ResilientStore {
+    backend: StoreBackend,
+    config: CacheConfig,
+    state: Mutex<HealthState>,
+}
+
+impl ResilientStore {
+    /// Opens the backend, starting in the unhealthy state if that fails
+    pub fn open(backend: StoreBackend, config: &CacheConfig) -> Self {
+        let store = ResilientStore {
+            backend,
+            config: config.clone(),
+            state: Mutex::new(HealthState {
+                store: None,
+                failures: 0,
+                down_since: None,
+                retry_at: Instant::now(),
+                last_error: None,
+                eviction_listener: None,
+                expiry_paused: false,
+            }),
+        };
+
+        // Connect right away, so the console reports the state from the start
+        let _ = store.call(|_| Ok(()));
+        store
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HealthState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Backoff after the given number of failures in a row
+    fn backoff(&self, failures: u32) -> Duration {
+        let backoff = self.config.l2_retry_min.saturating_mul(1 << failures.saturating_sub(1).min(16));
+        backoff.min(self.config.l2_retry_max)
+    }
+
+    /// Runs an operation on the backend unless it is unhealthy and not due for a retry
+    fn call<T>(&self, op: impl FnOnce(&dyn CacheStore) -> Result<T, CacheError>) -> Result<T, CacheError> {
+        let store = {
+            let mut state = self.lock();
+            let now = Instant::now();
+            if state.failures > 0 {
+                if now < state.retry_at {
+                    return Err(CacheError::Unavailable(format!(
+                        "{} retrying in {}s",
+                        self.backend.name(),
+                        (state.retry_at - now).as_secs() + 1
+                    )));
+                }
+                // This call is the probe, the others keep failing fast meanwhile
+                state.retry_at = now + self.backoff(state.failures);
+            }
+            state.store.clone()
+        };
+
+        let store = match store {
+            Some(store) => store,
+            None => match self.backend.open(&self.config) {
+                Ok(store) => {
+                    let mut state = self.lock();
+                    if let Some(listener) = &state.eviction_listener {
+                        store.set_eviction_listener(listener.clone());
+                    }
+                    store.set_expiry_paused(state.expiry_paused);
+                    state.store = Some(store.clone());
+                    store
+                },
+                Err(e) => {
+                    self.record_failure(&e);
+                    return Err(e);
+                }
+            },
+        };
+
+        let result = op(store.as_ref());
+        match &result {
+            Err(e) if e.is_unavailable() => self.record_failure(e),
+            _ => self.record_success(),
+        }
+        result
+    }
+
+    fn record_failure(&self, error: &CacheError) {
+        let mut state = self.lock();
+        state.failures += 1;
+        state.retry_at = Instant::now() + self.backoff(state.failures);
+        state.last_error = Some(error.to_string());
+        if state.down_since.is_none() {
+            state.down_since = Some(Instant::now());
+            println!(
+                "⚠️ L2 cache ({}) unavailable: {}, retrying in {}s",
+                self.backend.name(),
+                error,
+                self.backoff(state.failures).as_secs_f64()
+            );
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.lock();
+        if state.failures == 0 {
+            return;
+        }
+        if let Some(down_since) = state.down_since {
+            println!(
+                "✅ L2 cache ({}) is back after {}s",
+                self.backend.name(),
+                down_since.elapsed().as_secs()
+            );
+        }
+        state.failures = 0;
+        state.down_since = None;
+        state.last_error = None;
+    }
+}
+
+impl CacheStore for ResilientStore {
+    fn name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<CacheEntry>, CacheError> {
+        self.call(|store| store.get(key))
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, CacheError> {
+        self.call(|store| store.contains(key))
+    }
+
+    fn put(&self, key: &str, entry: &CacheEntry, retain_until: Option<u64>) -> Result<(), CacheError> {
+        self.call(|store| store.put(key, entry, retain_until))
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        self.call(|store| store.delete(key))
+    }
+
+    fn clear(&self) -> Result<(), CacheError> {
+        self.call(|store| store.clear())
+    }
+
+    fn scan(&self) -> Result<Vec<String>, CacheError> {
+        self.call(|store| store.scan())
+    }
+
+    fn peek(&self, key: &str) -> Result<Option<StoredEntry>, CacheError> {
+        self.call(|store| store.peek(key))
+    }
+
+    fn export(&self, key: &str) -> Result<Option<(CacheEntry, Option<u64>)>, CacheError> {
+        self.call(|store| store.export(key))
+    }
+
+    fn entries(&self) -> Result<Vec<StoredEntry>, CacheError> {
+        self.call(|store| store.entries())
+    }
+
+    fn stats(&self) -> Result<StoreStats, CacheError> {
+        self.call(|store| store.stats())
+    }
+
+    fn set_eviction_listener(&self, listener: EvictionListener) {
+        let mut state = self.lock();
+        if let Some(store) = &state.store {
+            store.set_eviction_listener(listener.clone());
+        }
+        state.eviction_listener = Some(listener);
+    }
+
+    fn set_expiry_paused(&self, paused: bool) {
+        let mut state = self.lock();
+        if let Some(store) = &state.store {
+            store.set_expiry_paused(paused);
+        }
+        state.expiry_paused = paused;
+    }
+
+    fn health(&self) -> StoreHealth {
+        let state = self.lock();
+        match state.down_since {
+            None => StoreHealth::Healthy,
+            Some(down_since) => StoreHealth::Unhealthy {
+                down_for: down_since.elapsed(),
+                retry_in: state.retry_at.saturating_duration_since(Instant::now()),
+                error: state.last_error.clone().unwrap_or_default(),
+            },
+        }
+    }
+
+    fn shutdown(&self) {
+        if let Some(store) = self.lock().store.clone() {
+            store.shutdown();
+        }
+    }
+}
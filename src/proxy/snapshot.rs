@@ -0,0 +1,304 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::proxy::cache::{now_secs, CacheEntry};
+use crate::proxy::codec::{decode_entry, encode_meta};
+use crate::proxy::store::{CacheError, CacheStore};
+
+// Portable snapshots of the whole cache, a gzip stream of:
+// 8 bytes magic "ROXYSNAP", u8 snapshot version
+// per entry: u8 tier (1 = L1, 2 = L2), str key, opt<u64> retain_until, bytes metadata, bytes body
+// u8 0 after the last entry
+// where str/bytes are a u32 length followed by the data, opt<T> a u8 flag followed by T if set
+// (little endian, metadata as encoded by codec::encode_meta, bodies as stored, possibly compressed)
+
+const MAGIC: &[u8; 8] = b"ROXYSNAP";
+const SNAPSHOT_VERSION: u8 = 1;
+const END_OF_ENTRIES: u8 = 0;
+
+/// Cache tier an archived entry came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotTier {
+    L1,
+    L2,
+}
+
+impl SnapshotTier {
+    fn tag(self) -> u8 {
+        match self {
+            SnapshotTier::L1 => 1,
+            SnapshotTier::L2 => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(SnapshotTier::L1),
+            2 => Some(SnapshotTier::L2),
+            _ => None,
+        }
+    }
+}
+
+/// What an export or import did
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnapshotReport {
+    pub l1_entries: usize,
+    pub l2_entries: usize,
+
+    /// Body bytes written or read
+    pub bytes: u64,
+
+    /// Entries left out: expired, unreadable, or rejected by the store
+    pub skipped: usize,
+}
+
+impl fmt::Display for SnapshotReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} L1 and {} L2 entries, {} body bytes, {} skipped",
+            self.l1_entries, self.l2_entries, self.bytes, self.skipped
+        )
+    }
+}
+
+/// Writes every entry of the tiers to an archive file, replacing it only once complete
+pub fn write_snapshot(path: &Path, tiers: &[(SnapshotTier, &dyn CacheStore)]) -> Result<SnapshotReport, CacheError> {
+    let temp_path = path.with_extension("tmp");
+    let result = (|| {
+        let mut out = GzEncoder::new(BufWriter::new(File::create(&temp_path)?), Compression::default());
+        out.write_all(MAGIC)?;
+        out.write_all(&[SNAPSHOT_VERSION])?;
+
+        let mut report = SnapshotReport::default();
+        for (tier, store) in tiers {
+            for key in store.scan()? {
+                // Dropped since the scan or past its retention time
+                let (entry, retain_until) = match store.export(&key)? {
+                    Some(exported) => exported,
+                    None => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                };
+                write_entry(&mut out, *tier, &key, &entry, retain_until)?;
+                report.bytes += entry.response_data.len() as u64;
+                match tier {
+                    SnapshotTier::L1 => report.l1_entries += 1,
+                    SnapshotTier::L2 => report.l2_entries += 1,
+                }
+            }
+        }
+
+        out.write_all(&[END_OF_ENTRIES])?;
+        out.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(report)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Reads an archive file, handing every entry that hasn't expired yet to `import`
+///
+/// `import` returns false if it didn't store the entry
+pub fn read_snapshot<F>(path: &Path, mut import: F) -> Result<SnapshotReport, CacheError>
+where
+    F: FnMut(SnapshotTier, &str, CacheEntry, Option<u64>) -> bool,
+{
+    let mut input = GzDecoder::new(BufReader::new(File::open(path)?));
+
+    // Other files fail here, gzipped or not
+    let mut magic = [0u8; 8];
+    if input.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(CacheError::Serialization(format!("{} is not a roxy cache snapshot", path.display())));
+    }
+    let version = read_u8(&mut input)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(CacheError::Serialization(format!("Unsupported snapshot version {}", version)));
+    }
+
+    let mut report = SnapshotReport::default();
+    loop {
+        let tag = read_u8(&mut input)?;
+        if tag == END_OF_ENTRIES {
+            break;
+        }
+        let tier = SnapshotTier::from_tag(tag)
+            .ok_or_else(|| CacheError::Serialization(format!("Invalid tier {} in snapshot", tag)))?;
+        let key = String::from_utf8(read_bytes(&mut input)?)
+            .map_err(|_| CacheError::Serialization("Invalid UTF-8 in snapshot key".to_string()))?;
+        let retain_until = match read_u8(&mut input)? {
+            0 => None,
+            _ => Some(read_u64(&mut input)?),
+        };
+        let meta = read_bytes(&mut input)?;
+        let body = read_bytes(&mut input)?;
+
+        if retain_until.map(|retain_until| retain_until <= now_secs()).unwrap_or(false) {
+            report.skipped += 1;
+            continue;
+        }
+        let entry = match decode_entry(&meta, body) {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Skipping snapshot entry {}: {}", key, e);
+                report.skipped += 1;
+                continue;
+            }
+        };
+
+        let bytes = entry.response_data.len() as u64;
+        if !import(tier, &key, entry, retain_until) {
+            report.skipped += 1;
+            continue;
+        }
+        report.bytes += bytes;
+        match tier {
+            SnapshotTier::L1 => report.l1_entries += 1,
+            SnapshotTier::L2 => report.l2_entries += 1,
+        }
+    }
+    Ok(report)
+}
+
+fn write_entry<W: Write>(out: &mut W, tier: SnapshotTier, key: &str, entry: &CacheEntry, retain_until: Option<u64>) -> Result<(), CacheError> {
+    out.write_all(&[tier.tag()])?;
+    write_bytes(out, key.as_bytes())?;
+    match retain_until {
+        Some(retain_until) => {
+            out.write_all(&[1])?;
+            out.write_all(&retain_until.to_le_bytes())?;
+        },
+        None => out.write_all(&[0])?,
+    }
+    write_bytes(out, &encode_meta(entry))?;
+    write_bytes(out, &entry.response_data)
+}
+
+fn write_bytes<W: Write>(out: &mut W, data: &[u8]) -> Result<(), CacheError> {
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+/// Reports a stream ending mid-entry as a broken snapshot rather than an I/O failure
+fn truncated(e: std::io::Error) -> CacheError {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        CacheError::Serialization("Truncated cache snapshot".to_string())
+    } else {
+        CacheError::Io(e)
+    }
+}
+
+fn read_u8<R: Read>(input: &mut R) -> Result<u8, CacheError> {
+    let mut byte = [0u8; 1];
+    input.read_exact(&mut byte).map_err(truncated)?;
+    Ok(byte[0])
+}
+
+fn read_u64<R: Read>(input: &mut R) -> Result<u64, CacheError> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_bytes<R: Read>(input: &mut R) -> Result<Vec<u8>, CacheError> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len).map_err(truncated)?;
+    let len = u32::from_le_bytes(len) as u64;
+
+    // Grows with the data actually read, a corrupt length can't allocate gigabytes up front
+    let mut data = Vec::new();
+    input.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(CacheError::Serialization("Truncated cache snapshot".to_string()));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::proxy::eviction::EvictionPolicy;
+    use crate::proxy::memory_store::MemoryStore;
+
+    /// Path in the temp directory, unique per test and process
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("roxy-snapshot-{}-{}", name, std::process::id()))
+    }
+
+    fn read_all(path: &Path) -> Result<Vec<(SnapshotTier, String, Vec<u8>)>, CacheError> {
+        let mut entries = Vec::new();
+        read_snapshot(path, |tier, key, entry, _| {
+            entries.push((tier, key.to_string(), entry.response_data));
+            true
+        })?;
+        Ok(entries)
+    }
+
+    #[test]
+    fn round_trip_keeps_tiers_and_bodies() {
+        let l1 = MemoryStore::new(EvictionPolicy::Lru, 1 << 20);
+        let l2 = MemoryStore::new(EvictionPolicy::Lru, 1 << 20);
+        let entry = CacheEntry::new(b"HTTP/1.1 200 OK\r\n\r\nhi".to_vec(), HashMap::new());
+        l1.put("http://a/", &entry, None).unwrap();
+        l2.put("http://b/", &entry, Some(now_secs() + 60)).unwrap();
+
+        let path = temp_file("round-trip");
+        let written = write_snapshot(&path, &[(SnapshotTier::L1, &l1), (SnapshotTier::L2, &l2)]).unwrap();
+        assert_eq!((written.l1_entries, written.l2_entries), (1, 1));
+
+        let mut entries = read_all(&path).unwrap();
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(entries[0], (SnapshotTier::L1, "http://a/".to_string(), entry.response_data.clone()));
+        assert_eq!(entries[1].0, SnapshotTier::L2);
+
+        l1.shutdown();
+        l2.shutdown();
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn header_is_checked() {
+        let path = temp_file("header");
+
+        // Not gzip at all
+        fs::write(&path, b"ROXYSNAP\x01\x00").unwrap();
+        assert!(read_all(&path).is_err());
+
+        let write_gzipped = |data: &[u8]| {
+            let mut out = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+            out.write_all(data).unwrap();
+            out.finish().unwrap();
+        };
+
+        write_gzipped(b"NOTROXY!\x01\x00");
+        assert!(read_all(&path).is_err());
+
+        write_gzipped(&[&MAGIC[..], &[SNAPSHOT_VERSION + 1, END_OF_ENTRIES]].concat());
+        assert!(read_all(&path).is_err());
+
+        // Valid header, cut off before the end marker
+        write_gzipped(&[&MAGIC[..], &[SNAPSHOT_VERSION]].concat());
+        assert!(matches!(read_all(&path), Err(CacheError::Serialization(_))));
+
+        write_gzipped(&[&MAGIC[..], &[SNAPSHOT_VERSION, END_OF_ENTRIES]].concat());
+        assert!(read_all(&path).unwrap().is_empty());
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use redis::RedisError;
+
+use crate::proxy::cache::{CacheConfig, CacheEntry};
+use crate::proxy::disk_store::DiskStore;
+use crate::proxy::l1::EvictionStats;
+use crate::proxy::memory_store::MemoryStore;
+use crate::proxy::redis_store::RedisStore;
+
+// Storage backends HttpCache is composed of:
+// CacheStore - what a tier (L1 or L2) has to support
+// StoreBackend - which implementation a tier uses (set in CacheConfig)
+// CacheError - failures of a backend
+// StoreHealth - whether a backend is reachable (see ResilientStore)
+
+/// Which implementation a cache tier uses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreBackend {
+    /// In-process map bounded by the eviction policy
+    Memory,
+
+    /// Redis server configured by `CacheConfig::redis`
+    Redis,
+
+    /// Files under `CacheConfig::disk_path`
+    Disk,
+}
+
+impl StoreBackend {
+    /// Parses a backend name as used on the command line
+    pub fn from_name(name: &str) -> Option<StoreBackend> {
+        match name.to_lowercase().as_str() {
+            "memory" => Some(StoreBackend::Memory),
+            "redis" => Some(StoreBackend::Redis),
+            "disk" => Some(StoreBackend::Disk),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StoreBackend::Memory => "memory",
+            StoreBackend::Redis => "redis",
+            StoreBackend::Disk => "disk",
+        }
+    }
+
+    /// Creates a store of this kind from the cache configuration
+    pub fn open(self, config: &CacheConfig) -> Result<Arc<dyn CacheStore>, CacheError> {
+        Ok(match self {
+            StoreBackend::Memory => Arc::new(MemoryStore::new(config.eviction_policy, config.l1_max_bytes)),
+            StoreBackend::Redis => Arc::new(RedisStore::open(&config.redis)?),
+            StoreBackend::Disk => Arc::new(DiskStore::open(&config.disk_path, config.disk_max_bytes)?),
+        })
+    }
+}
+
+/// Why a cache store operation failed
+#[derive(Debug)]
+pub enum CacheError {
+    /// Redis command or connection failed
+    Redis(RedisError),
+
+    /// Reading or writing the disk store failed
+    Io(io::Error),
+
+    /// A stored entry could not be encoded or decoded
+    Serialization(String),
+
+    /// The backend is marked unhealthy and waits for its next reconnect attempt
+    Unavailable(String),
+
+    /// The backend is reachable but had no capacity for the call in time (e.g. connection pool exhausted)
+    Busy(String),
+}
+
+impl CacheError {
+    /// Checks if the error means the backend can't be reached (as opposed to a bad entry)
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            CacheError::Redis(e) => e.is_io_error() || e.is_connection_refusal() || e.is_timeout() || e.is_connection_dropped(),
+            CacheError::Io(_) => true,
+            CacheError::Serialization(_) => false,
+            CacheError::Unavailable(_) => true,
+            CacheError::Busy(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Redis(e) => write!(f, "Redis error: {}", e),
+            CacheError::Io(e) => write!(f, "I/O error: {}", e),
+            CacheError::Serialization(e) => write!(f, "Serialization error: {}", e),
+            CacheError::Unavailable(e) => write!(f, "Store unavailable: {}", e),
+            CacheError::Busy(e) => write!(f, "Store busy: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<RedisError> for CacheError {
+    fn from(e: RedisError) -> Self {
+        CacheError::Redis(e)
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(e: serde_json::Error) -> Self {
+        CacheError::Serialization(e.to_string())
+    }
+}
+
+/// Size and counters of a store
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StoreStats {
+    pub entries: usize,
+
+    /// Bytes used, if the backend knows
+    pub bytes: Option<usize>,
+
+    /// Evictions, admission rejections and expirations (memory store only)
+    pub evictions: EvictionStats,
+}
+
+/// Called with the key of every entry a store evicts to make room for others
+pub type EvictionListener = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Metadata of a stored entry, as shown by the inspection commands
+#[derive(Clone, Debug)]
+pub struct StoredEntry {
+    pub key: String,
+
+    /// The entry with its `response_data` left empty
+    pub entry: CacheEntry,
+
+    /// Stored body length (after compression)
+    pub size: usize,
+
+    /// Hits counted by the store, None if it doesn't count them
+    pub hits: Option<usize>,
+
+    /// When the store drops the entry, None if unknown or kept until evicted
+    pub retain_until: Option<u64>,
+}
+
+impl StoredEntry {
+    /// Metadata of an entry read through `get`, for stores without a cheaper way
+    pub fn from_entry(key: &str, mut entry: CacheEntry) -> Self {
+        let size = std::mem::take(&mut entry.response_data).len();
+        StoredEntry {
+            key: key.to_string(),
+            entry,
+            size,
+            hits: None,
+            retain_until: None,
+        }
+    }
+}
+
+/// Reachability of a store
+#[derive(Clone, Debug)]
+pub enum StoreHealth {
+    Healthy,
+    Unhealthy {
+        /// Time since the backend failed
+        down_for: Duration,
+        /// Time until the next reconnect attempt
+        retry_in: Duration,
+        /// Last error
+        error: String,
+    },
+}
+
+///
+/// Key-value storage for cache entries, used as the L1 or L2 tier of HttpCache
+///
+/// Stores drop entries on their own once `retain_until` (UNIX seconds) has passed
+///
+pub trait CacheStore: Send + Sync {
+    /// Backend name for logs and the console
+    fn name(&self) -> &'static str;
+
+    /// Looks up an entry, None if it is missing or past `retain_until`
+    fn get(&self, key: &str) -> Result<Option<CacheEntry>, CacheError>;
+
+    /// Checks if a key is stored (without counting it as an access)
+    fn contains(&self, key: &str) -> Result<bool, CacheError>;
+
+    /// Stores an entry until `retain_until` (None keeps it until it is evicted or deleted)
+    fn put(&self, key: &str, entry: &CacheEntry, retain_until: Option<u64>) -> Result<(), CacheError>;
+
+    /// Removes an entry
+    ///
+    /// - returns true if it was stored
+    fn delete(&self, key: &str) -> Result<bool, CacheError>;
+
+    /// Removes every entry
+    fn clear(&self) -> Result<(), CacheError>;
+
+    /// All stored keys
+    fn scan(&self) -> Result<Vec<String>, CacheError>;
+
+    /// Metadata of an entry, without counting it as a hit where the store can avoid it
+    fn peek(&self, key: &str) -> Result<Option<StoredEntry>, CacheError> {
+        Ok(self.get(key)?.map(|entry| StoredEntry::from_entry(key, entry)))
+    }
+
+    /// Full entry with the time the store drops it, for snapshots
+    ///
+    /// Doesn't count as a hit where the store can avoid it
+    fn export(&self, key: &str) -> Result<Option<(CacheEntry, Option<u64>)>, CacheError> {
+        let retain_until = self.peek(key)?.and_then(|stored| stored.retain_until);
+        Ok(self.get(key)?.map(|entry| (entry, retain_until)))
+    }
+
+    /// Metadata of every entry
+    fn entries(&self) -> Result<Vec<StoredEntry>, CacheError> {
+        let mut entries = Vec::new();
+        for key in self.scan()? {
+            if let Some(entry) = self.peek(&key)? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn stats(&self) -> Result<StoreStats, CacheError>;
+
+    /// Registers the listener for evictions, stores that never evict ignore it
+    fn set_eviction_listener(&self, _listener: EvictionListener) {}
+
+    /// Stops or resumes dropping entries past their retention time (offline mode),
+    /// stores whose backend expires entries on its own ignore it
+    fn set_expiry_paused(&self, _paused: bool) {}
+
+    /// Reachability of the backend, stores without a remote backend are always healthy
+    fn health(&self) -> StoreHealth {
+        StoreHealth::Healthy
+    }
+
+    /// Stops background work of the store
+    fn shutdown(&self) {}
+}
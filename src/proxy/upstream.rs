@@ -0,0 +1,497 @@
+use std::fmt;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::utils::responses::{ErrorResponse, Status};
+use crate::utils::stats::UpstreamFailure;
+
+// Connects to upstream servers and reads their responses within configured timeouts
+
+///
+/// Timeouts for talking to upstream servers
+///
+#[derive(Clone, Copy, Debug)]
+pub struct UpstreamTimeouts {
+    /// Maximum time for resolving the host name
+    pub dns: Duration,
+
+    /// Maximum time for establishing the TCP connection (per address, all addresses together stay within `total`)
+    pub connect: Duration,
+
+    /// Maximum time between sending the request and the first response byte
+    pub first_byte: Duration,
+
+    /// Maximum time from connecting to the end of the response head,
+    /// and the longest wait for more body once it streams (a long download doesn't time out while data flows)
+    pub total: Duration,
+}
+
+///
+/// Retry budget for idempotent requests whose upstream connection failed
+///
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt (0 disables retrying)
+    pub max_retries: u32,
+
+    /// Backoff before the first retry, doubled for every following one
+    pub base_backoff: Duration,
+
+    /// Upper bound for the backoff
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `retry` (starting at 0)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.base_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 2,
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl Default for UpstreamTimeouts {
+    fn default() -> Self {
+        UpstreamTimeouts {
+            dns: Duration::from_secs(5),
+            connect: Duration::from_secs(10),
+            first_byte: Duration::from_secs(30),
+            total: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Why talking to an upstream server failed
+#[derive(Debug)]
+pub enum UpstreamError {
+    DnsTimeout,
+    Dns(io::Error),
+    ConnectTimeout,
+    Connect(io::Error),
+    FirstByteTimeout,
+    TotalTimeout,
+    Io(io::Error),
+    /// The connection broke after part of the response arrived
+    Truncated(io::Error),
+}
+
+impl UpstreamError {
+    /// Category used for statistics
+    pub fn failure(&self) -> UpstreamFailure {
+        match self {
+            UpstreamError::DnsTimeout => UpstreamFailure::DnsTimeout,
+            UpstreamError::Dns(_) => UpstreamFailure::DnsError,
+            UpstreamError::ConnectTimeout => UpstreamFailure::ConnectTimeout,
+            UpstreamError::Connect(_) => UpstreamFailure::ConnectError,
+            UpstreamError::FirstByteTimeout => UpstreamFailure::FirstByteTimeout,
+            UpstreamError::TotalTimeout => UpstreamFailure::TotalTimeout,
+            UpstreamError::Io(_) | UpstreamError::Truncated(_) => UpstreamFailure::IoError,
+        }
+    }
+
+    /// Whether the request can be sent again: only failures before any response byte arrived qualify
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, UpstreamError::ConnectTimeout | UpstreamError::Connect(_) | UpstreamError::Io(_))
+    }
+
+    /// Timeouts become `504 Gateway Timeout`, everything else `502 Bad Gateway`
+    pub fn to_response(&self, host: &str) -> ErrorResponse {
+        let status = match self {
+            UpstreamError::DnsTimeout
+            | UpstreamError::ConnectTimeout
+            | UpstreamError::FirstByteTimeout
+            | UpstreamError::TotalTimeout => Status::GatewayTimeout,
+            _ => Status::BadGateway,
+        };
+        ErrorResponse::new(status).with_message(format!("{} ({})", self, host))
+    }
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::DnsTimeout => write!(f, "DNS lookup timed out"),
+            UpstreamError::Dns(e) => write!(f, "DNS lookup failed: {}", e),
+            UpstreamError::ConnectTimeout => write!(f, "Connecting to the server timed out"),
+            UpstreamError::Connect(e) => write!(f, "Could not connect to the server: {}", e),
+            UpstreamError::FirstByteTimeout => write!(f, "Server did not start responding in time"),
+            UpstreamError::TotalTimeout => write!(f, "Server did not finish responding in time"),
+            UpstreamError::Io(e) => write!(f, "Error talking to the server: {}", e),
+            UpstreamError::Truncated(e) => write!(f, "Server response was cut short: {}", e),
+        }
+    }
+}
+
+/// Resolves a `host:port` string, giving up after `timeout`
+pub fn resolve(host: &str, timeout: Duration) -> Result<Vec<SocketAddr>, UpstreamError> {
+    let (sender, receiver) = mpsc::channel();
+    let host_owned = host.to_string();
+
+    // The system resolver can't be cancelled, so it runs in its own thread
+    thread::spawn(move || {
+        let result = host_owned.to_socket_addrs().map(|addrs| addrs.collect::<Vec<_>>());
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(addrs)) if !addrs.is_empty() => Ok(addrs),
+        Ok(Ok(_)) => Err(UpstreamError::Dns(io::Error::new(ErrorKind::NotFound, "no addresses found"))),
+        Ok(Err(e)) => Err(UpstreamError::Dns(e)),
+        Err(_) => Err(UpstreamError::DnsTimeout),
+    }
+}
+
+/// Connects to the first reachable address of `host`
+pub fn connect(host: &str, timeouts: &UpstreamTimeouts) -> Result<TcpStream, UpstreamError> {
+    let started = Instant::now();
+    let addrs = resolve(host, timeouts.dns)?;
+    connect_addrs(&addrs, timeouts, started)
+}
+
+/// Connects to the first reachable address in `addrs`, in order
+///
+/// Every address gets the connect timeout, but all attempts together end with the total timeout counted from `started`
+pub fn connect_addrs(addrs: &[SocketAddr], timeouts: &UpstreamTimeouts, started: Instant) -> Result<TcpStream, UpstreamError> {
+    let deadline = started + timeouts.total;
+    let mut last_error = UpstreamError::ConnectTimeout;
+    for addr in addrs {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(UpstreamError::TotalTimeout);
+        }
+        match TcpStream::connect_timeout(addr, remaining.min(timeouts.connect)) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => {
+                last_error = UpstreamError::ConnectTimeout;
+            },
+            Err(e) => last_error = UpstreamError::Connect(e),
+        }
+    }
+    Err(last_error)
+}
+
+/// Sends a request, giving up when the total timeout counted from `started` runs out
+pub fn write_request(server_stream: &mut TcpStream, request: &[u8], timeouts: &UpstreamTimeouts, started: Instant) -> Result<(), UpstreamError> {
+    let remaining = (started + timeouts.total).saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(UpstreamError::TotalTimeout);
+    }
+    server_stream.set_write_timeout(Some(remaining)).map_err(UpstreamError::Io)?;
+
+    match server_stream.write_all(request) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Err(UpstreamError::TotalTimeout),
+        Err(e) => Err(UpstreamError::Io(e)),
+    }
+}
+
+/// Largest response head accepted from a server
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Bytes read from the server at a time
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+///
+/// Response whose head has arrived, the body is read piece by piece as the caller forwards it
+///
+pub struct UpstreamResponse {
+    stream: TcpStream,
+
+    /// Status line and headers
+    head: Vec<u8>,
+
+    /// Bytes read with the head and not handed out yet
+    pending: Vec<u8>,
+
+    /// Last piece handed out by `next_chunk`
+    chunk: Vec<u8>,
+
+    framing: BodyFraming,
+    idle_timeout: Duration,
+}
+
+impl UpstreamResponse {
+    /// Status line and headers, including the blank line ending them
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Next piece of the raw response, starting with the head
+    ///
+    /// - returns None once the body is complete
+    pub fn next_chunk(&mut self) -> Result<Option<&[u8]>, UpstreamError> {
+        if !self.pending.is_empty() {
+            self.chunk = std::mem::take(&mut self.pending);
+            return Ok(Some(&self.chunk));
+        }
+        if matches!(self.framing, BodyFraming::Done) {
+            return Ok(None);
+        }
+
+        // The total timeout bounds every wait for more body, not the whole transfer
+        self.stream.set_read_timeout(Some(self.idle_timeout)).map_err(UpstreamError::Io)?;
+        self.chunk.resize(READ_BUFFER_SIZE, 0);
+        loop {
+            match self.stream.read(&mut self.chunk) {
+                Ok(0) if matches!(self.framing, BodyFraming::UntilClose) => {
+                    self.framing = BodyFraming::Done;
+                    return Ok(None);
+                },
+                Ok(0) => {
+                    return Err(UpstreamError::Truncated(io::Error::new(ErrorKind::UnexpectedEof, "connection closed before the end of the body")));
+                },
+                Ok(n) => {
+                    let used = self.framing.advance(&self.chunk[..n]);
+                    self.chunk.truncate(used);
+                    return Ok(Some(&self.chunk));
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => return Err(UpstreamError::TotalTimeout),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(UpstreamError::Truncated(e)),
+            }
+        }
+    }
+
+    /// Reads the whole response, giving up once it is larger than `limit` bytes
+    ///
+    /// - returns None if the response is larger than `limit`
+    pub fn read_to_end(mut self, limit: usize) -> Result<Option<Vec<u8>>, UpstreamError> {
+        let mut response = Vec::new();
+        while let Some(chunk) = self.next_chunk()? {
+            if response.len() + chunk.len() > limit {
+                return Ok(None);
+            }
+            response.extend_from_slice(chunk);
+        }
+        Ok(Some(response))
+    }
+}
+
+/// Reads a response up to the end of its head
+/// # Parameters
+/// * server_stream - connection the request was written to
+/// * method - request method, responses to HEAD have no body
+/// * timeouts - first byte timeout applies to the first read, total timeout to the head and to every wait for more body
+/// * started - when the exchange started, the total timeout for the head counts from here
+///
+/// - returns the response, its body is read with `next_chunk`
+pub fn read_response_head(mut server_stream: TcpStream, method: &str, timeouts: &UpstreamTimeouts, started: Instant) -> Result<UpstreamResponse, UpstreamError> {
+    let deadline = started + timeouts.total;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 8192];
+
+    let head_end = loop {
+        if let Some(position) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        if received.len() > MAX_HEAD_SIZE {
+            return Err(UpstreamError::Truncated(io::Error::new(ErrorKind::InvalidData, "response head too large")));
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(UpstreamError::TotalTimeout);
+        }
+        let read_timeout = if received.is_empty() { remaining.min(timeouts.first_byte) } else { remaining };
+        server_stream.set_read_timeout(Some(read_timeout)).map_err(UpstreamError::Io)?;
+
+        match server_stream.read(&mut buffer) {
+            Ok(0) if received.is_empty() => {
+                return Err(UpstreamError::Io(io::Error::new(ErrorKind::UnexpectedEof, "empty response")));
+            },
+            Ok(0) => {
+                return Err(UpstreamError::Truncated(io::Error::new(ErrorKind::UnexpectedEof, "connection closed in the response head")));
+            },
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                return Err(if received.is_empty() && read_timeout < remaining {
+                    UpstreamError::FirstByteTimeout
+                } else {
+                    UpstreamError::TotalTimeout
+                });
+            },
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if received.is_empty() => return Err(UpstreamError::Io(e)),
+            Err(e) => return Err(UpstreamError::Truncated(e)),
+        }
+    };
+
+    let head = received[..head_end].to_vec();
+    let mut framing = BodyFraming::from_head(&head, method);
+    // Body bytes that arrived with the head, anything after the body isn't ours
+    let body_len = framing.advance(&received[head_end..]);
+    received.truncate(head_end + body_len);
+
+    Ok(UpstreamResponse {
+        stream: server_stream,
+        head,
+        pending: received,
+        chunk: Vec::new(),
+        framing,
+        idle_timeout: timeouts.total,
+    })
+}
+
+/// How the end of a response body is recognised
+enum BodyFraming {
+    /// Nothing left to read
+    Done,
+
+    /// Bytes of a Content-Length body still to come
+    Length(u64),
+
+    /// Chunked transfer coding, walked chunk by chunk
+    Chunked(ChunkState),
+
+    /// No framing information, the body ends when the server closes
+    UntilClose,
+}
+
+/// Position within a chunked body
+enum ChunkState {
+    /// Chunk size line read so far (hex size, optionally followed by extensions)
+    Size(Vec<u8>),
+
+    /// Chunk data bytes still to come
+    Data(u64),
+
+    /// Bytes of the CRLF after chunk data still to come
+    DataEnd(u8),
+
+    /// Trailer line read so far, the section ends with an empty line
+    Trailer(Vec<u8>),
+}
+
+/// Longest chunk size or trailer line accepted, longer ones are treated as unframed
+const MAX_CHUNK_LINE: usize = 8192;
+
+impl BodyFraming {
+    fn from_head(head: &[u8], method: &str) -> Self {
+        let head = String::from_utf8_lossy(head).to_lowercase();
+
+        // Responses that never have a body
+        let status = head.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+        if method.eq_ignore_ascii_case("HEAD") || matches!(status, Some(100..=199) | Some(204) | Some(304)) {
+            return BodyFraming::Done;
+        }
+
+        // Transfer-Encoding overrides Content-Length (RFC 9112 §6.3)
+        let mut length = None;
+        for line in head.lines() {
+            if line.starts_with("transfer-encoding:") && line.contains("chunked") {
+                return BodyFraming::Chunked(ChunkState::Size(Vec::new()));
+            }
+            if let Some(value) = line.strip_prefix("content-length:") {
+                length = value.trim().parse::<u64>().ok();
+            }
+        }
+        match length {
+            Some(0) => BodyFraming::Done,
+            Some(length) => BodyFraming::Length(length),
+            None => BodyFraming::UntilClose,
+        }
+    }
+
+    /// Consumes body bytes as they arrive
+    ///
+    /// - returns how many of them belong to the body, fewer than given only once it is complete
+    fn advance(&mut self, data: &[u8]) -> usize {
+        match self {
+            BodyFraming::Done => 0,
+            BodyFraming::UntilClose => data.len(),
+            BodyFraming::Length(remaining) => {
+                let used = (*remaining).min(data.len() as u64);
+                *remaining -= used;
+                if *remaining == 0 {
+                    *self = BodyFraming::Done;
+                }
+                used as usize
+            },
+            BodyFraming::Chunked(state) => {
+                let mut position = 0;
+                while position < data.len() {
+                    match state {
+                        ChunkState::Size(line) => {
+                            line.push(data[position]);
+                            position += 1;
+                            if !line.ends_with(b"\r\n") {
+                                if line.len() > MAX_CHUNK_LINE {
+                                    *self = BodyFraming::UntilClose;
+                                    return data.len();
+                                }
+                                continue;
+                            }
+                            let size_line = String::from_utf8_lossy(&line[..line.len() - 2]).to_string();
+                            match u64::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16) {
+                                Ok(0) => *state = ChunkState::Trailer(Vec::new()),
+                                Ok(size) => *state = ChunkState::Data(size),
+                                // Malformed, read until the server closes
+                                Err(_) => {
+                                    *self = BodyFraming::UntilClose;
+                                    return data.len();
+                                }
+                            }
+                        },
+                        ChunkState::Data(remaining) => {
+                            let used = (*remaining).min((data.len() - position) as u64);
+                            position += used as usize;
+                            *remaining -= used;
+                            if *remaining == 0 {
+                                *state = ChunkState::DataEnd(2);
+                            }
+                        },
+                        ChunkState::DataEnd(remaining) => {
+                            position += 1;
+                            *remaining -= 1;
+                            if *remaining == 0 {
+                                *state = ChunkState::Size(Vec::new());
+                            }
+                        },
+                        ChunkState::Trailer(line) => {
+                            line.push(data[position]);
+                            position += 1;
+                            if line.as_slice() == b"\r\n" {
+                                *self = BodyFraming::Done;
+                                return position;
+                            }
+                            if line.ends_with(b"\r\n") {
+                                line.clear();
+                            } else if line.len() > MAX_CHUNK_LINE {
+                                *self = BodyFraming::UntilClose;
+                                return data.len();
+                            }
+                        },
+                    }
+                }
+                position
+            },
+        }
+    }
+}
+
+/// Whether a request method is safe, i.e. doesn't change state on the origin (RFC 9110 §9.2.1)
+pub fn is_safe(method: &str) -> bool {
+    matches!(method.to_ascii_uppercase().as_str(), "GET" | "HEAD" | "OPTIONS" | "TRACE")
+}
+
+/// Whether a request method is idempotent and may be retried (RFC 9110 §9.2.2)
+pub fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method.to_ascii_uppercase().as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+    )
+}
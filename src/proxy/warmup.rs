@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::http::forward_http_request;
+use crate::proxy::cache::HttpCache;
+use crate::proxy::config::ProxyConfig;
+use crate::utils::host_filtering::Blacklist;
+use crate::utils::parsing::{request_url, url_authority};
+use crate::utils::stats::ProxyStats;
+
+// Pre-populates the cache after a restart or `clear`: every URL read from a file is
+// requested with GET through the normal forwarding path and the response is discarded
+//
+// The file is either a list of absolute URLs (one per line, # starts a comment)
+// or an access log in common/combined format, whose quoted request lines are replayed
+
+/// Outcome of a warm-up
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WarmupReport {
+    /// Distinct URLs requested
+    pub requested: usize,
+
+    /// Responses the cache stored
+    pub stored: usize,
+
+    /// Responses the cache refused (no-store, already expired, too large)
+    pub rejected: usize,
+
+    /// URLs that were cached before
+    pub already_cached: usize,
+
+    /// Requests that failed
+    pub failed: usize,
+
+    /// Lines without a plain HTTP GET URL, and blacklisted hosts
+    pub skipped: usize,
+}
+
+impl fmt::Display for WarmupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} URLs: {} stored, {} rejected as uncacheable, {} already cached, {} failed, {} skipped",
+            self.requested, self.stored, self.rejected, self.already_cached, self.failed, self.skipped
+        )
+    }
+}
+
+/// Reads the distinct URLs of a URL list or access log, in order of first appearance
+///
+/// - returns the URLs and the number of lines skipped
+pub fn read_urls(path: &Path) -> io::Result<(Vec<String>, usize)> {
+    let content = fs::read_to_string(path)?;
+    let mut seen = HashSet::new();
+    let mut urls = Vec::new();
+    let mut skipped = 0;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line_url(line) {
+            Some(url) => {
+                if seen.insert(url.to_string()) {
+                    urls.push(url.to_string());
+                }
+            },
+            None => skipped += 1,
+        }
+    }
+    Ok((urls, skipped))
+}
+
+/// Plain HTTP URL of a list line or of a logged GET request
+fn line_url(line: &str) -> Option<&str> {
+    let url = if line.starts_with("http://") || line.starts_with("https://") {
+        line.split_whitespace().next()?
+    } else {
+        // Request line of the common log format: ... "GET http://host/path HTTP/1.1" ...
+        let request_line = line.split('"').nth(1)?;
+        let mut parts = request_line.split_whitespace();
+        if parts.next()? != "GET" {
+            return None;
+        }
+        parts.next()?
+    };
+    // HTTPS is tunnelled and never cached
+    url.starts_with("http://").then_some(url)
+}
+
+/// Requests every URL of the file with at most `concurrency` requests at a time
+pub fn warm_up(path: &Path, concurrency: usize, blacklist: &Blacklist, cache: &Arc<HttpCache>, stats: &Arc<ProxyStats>, config: &Arc<ProxyConfig>) -> io::Result<WarmupReport> {
+    let (urls, skipped) = read_urls(path)?;
+    println!("🔥 Warming up the cache with {} URLs from {}", urls.len(), path.display());
+
+    let report = Mutex::new(WarmupReport {
+        skipped,
+        ..WarmupReport::default()
+    });
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, urls.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(url) = urls.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let outcome = warm_url(url, blacklist, cache, stats, config);
+
+                    let mut report = match report.lock() {
+                        Ok(guard) => guard,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                    match outcome {
+                        Outcome::Stored => report.stored += 1,
+                        Outcome::Rejected => report.rejected += 1,
+                        Outcome::AlreadyCached => report.already_cached += 1,
+                        Outcome::Failed => report.failed += 1,
+                        Outcome::Skipped => {
+                            report.skipped += 1;
+                            continue;
+                        },
+                    }
+                    report.requested += 1;
+                }
+            });
+        }
+    });
+
+    Ok(match report.into_inner() {
+        Ok(report) => report,
+        Err(poisoned) => poisoned.into_inner(),
+    })
+}
+
+enum Outcome {
+    Stored,
+    Rejected,
+    AlreadyCached,
+    Failed,
+    Skipped,
+}
+
+/// Fetches one URL the way a client request for it would be
+fn warm_url(url: &str, blacklist: &Blacklist, cache: &Arc<HttpCache>, stats: &Arc<ProxyStats>, config: &Arc<ProxyConfig>) -> Outcome {
+    let authority = url_authority(url);
+    let path = match &url["http://".len() + authority.len()..] {
+        path if path.starts_with('/') => path.to_string(),
+        path => format!("/{}", path),
+    };
+    // Same form as extract_host, so the cache key matches the one of client requests
+    let host = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    if blacklist.has(&host) {
+        return Outcome::Skipped;
+    }
+
+    let key = request_url(&path, &host);
+    if cache.contains(&key) {
+        return Outcome::AlreadyCached;
+    }
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: roxy-warmup\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    if let Err(e) = forward_http_request(host, request.as_bytes(), &mut io::sink(), Arc::clone(cache), Arc::clone(stats), Arc::clone(config)) {
+        println!("Warm-up of {} failed: {}", url, e);
+        return Outcome::Failed;
+    }
+
+    if cache.contains(&key) {
+        Outcome::Stored
+    } else {
+        Outcome::Rejected
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use serde_json::json;
+
+use crate::utils::parsing::get_header;
+use crate::utils::responses::escape_html;
+
+// module for the page shown when a request is blocked by the blacklist
+
+/// Built-in block page used when no template file is configured
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Access Denied</title>
+</head>
+<body>
+<h1>Access Denied</h1>
+<p>The request to <b>{{host}}</b> was blocked by roxy.</p>
+<ul>
+<li>URL: {{url}}</li>
+<li>Matched rule: {{rule}}</li>
+<li>Client: {{client_ip}}</li>
+<li>Time: {{timestamp}}</li>
+</ul>
+</body>
+</html>
+"#;
+
+/// Details about a blocked request that get substituted into the block page
+pub struct BlockInfo {
+    pub host: String,
+    pub url: String,
+    pub rule: String,
+    pub client_ip: String,
+    pub timestamp: String,
+}
+
+/// Representation picked from the client's `Accept` header
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    Html,
+    Json,
+    Text,
+}
+
+/// HTML block page template with `{{host}}`, `{{url}}`, `{{rule}}`, `{{client_ip}}` and `{{timestamp}}` placeholders
+pub struct BlockPage {
+    template: String,
+}
+
+impl BlockPage {
+    /// Loads the template from an HTML file
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            template: fs::read_to_string(path)?,
+        })
+    }
+
+    /// Renders the block page in the format the client asked for
+    ///
+    /// - returns (content type, body)
+    pub fn render(&self, info: &BlockInfo, request_headers: &HashMap<String, String>) -> (&'static str, String) {
+        match negotiate_format(get_header(request_headers, "accept").map(|s| s.as_str())) {
+            BlockFormat::Html => ("text/html; charset=utf-8", self.render_html(info)),
+            BlockFormat::Json => {
+                let body = json!({
+                    "error": "blocked",
+                    "host": info.host,
+                    "url": info.url,
+                    "rule": info.rule,
+                    "client_ip": info.client_ip,
+                    "timestamp": info.timestamp,
+                });
+                ("application/json", body.to_string())
+            },
+            BlockFormat::Text => (
+                "text/plain",
+                format!("Access Denied: Blacklisted\nURL: {}\nRule: {}\n", info.url, info.rule),
+            ),
+        }
+    }
+
+    /// Substitutes the placeholders in the HTML template
+    fn render_html(&self, info: &BlockInfo) -> String {
+        self.template
+            .replace("{{host}}", &escape_html(&info.host))
+            .replace("{{url}}", &escape_html(&info.url))
+            .replace("{{rule}}", &escape_html(&info.rule))
+            .replace("{{client_ip}}", &escape_html(&info.client_ip))
+            .replace("{{timestamp}}", &escape_html(&info.timestamp))
+    }
+}
+
+impl Default for BlockPage {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+}
+
+/// Picks the block page format from an `Accept` header, honoring q-values
+/// # Parameters
+/// * accept - value of the `Accept` header, if any
+///
+/// - returns BlockFormat (plain text when nothing matches)
+pub fn negotiate_format(accept: Option<&str>) -> BlockFormat {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return BlockFormat::Text,
+    };
+
+    let mut best = BlockFormat::Text;
+    let mut best_q = 0.0;
+
+    for media_range in accept.split(',') {
+        let mut params = media_range.split(';');
+        let media_type = params.next().unwrap_or("").trim().to_lowercase();
+        let q = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let format = match media_type.as_str() {
+            "text/html" | "application/xhtml+xml" => BlockFormat::Html,
+            "application/json" => BlockFormat::Json,
+            "text/plain" | "text/*" | "*/*" => BlockFormat::Text,
+            _ => continue,
+        };
+
+        // Browsers send */* alongside text/html, so prefer the more specific type on ties
+        if q > best_q || (q == best_q && q > 0.0 && format != BlockFormat::Text && best == BlockFormat::Text) {
+            best = format;
+            best_q = q;
+        }
+    }
+
+    best
+}
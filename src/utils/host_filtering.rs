@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 
-/// module allowing to blacklist add/remove/list hosts
+// module allowing to blacklist add/remove/list hosts
 
 /// Thread-safe blacklist using Arc + Mutex
 pub struct Blacklist {
@@ -23,8 +23,13 @@ impl Blacklist {
 
     /// Checks if a host is blacklisted
     pub fn has(&self, host: &str) -> bool {
+        self.matching_rule(host).is_some()
+    }
+
+    /// Returns the blacklist entry that blocks a host, if any
+    pub fn matching_rule(&self, host: &str) -> Option<String> {
         let blocked = self.blocked.lock().unwrap();
-        blocked.iter().any(|blocked_host| host.contains(blocked_host))
+        blocked.iter().find(|blocked_host| host.contains(blocked_host.as_str())).cloned()
     }
 
     /// Adds a host to the blacklist
@@ -59,3 +64,9 @@ impl Blacklist {
         }
     }
 }
+
+impl Default for Blacklist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
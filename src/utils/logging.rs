@@ -1,5 +1,24 @@
 // for the later logging
 
+use crate::utils::stats::TunnelRecord;
+
 pub fn log_request(request: &str) {
     println!("[REQUEST]:\n{}", request);
 }
+
+pub fn log_tunnel(record: &TunnelRecord) {
+    let ttfb = match record.time_to_first_byte {
+        Some(ttfb) => format!("{:.2?}", ttfb),
+        None => "-".to_string(),
+    };
+    println!(
+        "[TUNNEL] host={} client={} up={}B down={}B duration={:.2?} ttfb={} reason={}",
+        record.host,
+        record.client,
+        record.bytes_up,
+        record.bytes_down,
+        record.duration,
+        ttfb,
+        record.close_reason
+    );
+}
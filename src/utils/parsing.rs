@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-/// module used for functions that perform some kind of parsing
+// module used for functions that perform some kind of parsing
 
 
 /// Extracts host and port (443 or 80)
@@ -99,13 +99,13 @@ pub fn parse_http_response(raw_response: &str) -> Option<HttpResponse> {
         }
     }
 
-    return Some(HttpResponse {
+    Some(HttpResponse {
         version,
         status_code,
         status_text,
         headers,
-        body: body,
-    });
+        body,
+    })
 }
 
 
@@ -145,12 +145,232 @@ pub fn parse_http_request(raw_request: &str) -> Option<HttpRequest> {
         }
     }
 
-    return Some(HttpRequest {
+    Some(HttpRequest {
         method,
         path,
         version,
         headers,
-        body: body,
-    });
+        body,
+    })
+}
+
+
+/// Looks up a header by name, ignoring case
+/// # Parameters
+/// * headers - parsed request or response headers
+/// * name - header name in any case
+///
+/// - returns Option<&String>
+pub fn get_header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a String> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+
+/// Builds the absolute URL of a plain HTTP request
+/// # Parameters
+/// * path - request target, either origin-form (/index.html) or absolute-form (http://host/index.html)
+/// * host - host with port as returned by extract_host
+///
+/// - returns String
+pub fn request_url(path: &str, host: &str) -> String {
+    if path.starts_with("http://") || path.starts_with("https://") {
+        return path.to_string();
+    }
+    let host = host.strip_suffix(":80").unwrap_or(host);
+    format!("http://{}{}", host, path)
+}
+
+
+/// Extracts host and port of an absolute URL
+/// # Parameters
+/// * url - absolute URL (the whole value is treated as the authority if it has no scheme)
+///
+/// - returns &str, e.g. example.com or example.com:8080
+pub fn url_authority(url: &str) -> &str {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    rest.split(['/', '?']).next().unwrap_or(rest)
+}
+
+
+/// Resolves a URL reference (e.g. a Location header) against the URL of a request
+/// # Parameters
+/// * base - absolute URL as returned by request_url
+/// * reference - absolute URL, network-path (//host/path), absolute path or relative path
+///
+/// - returns Option<String>, None if the reference points to a different host
+pub fn resolve_same_host_url(base: &str, reference: &str) -> Option<String> {
+    let (scheme, rest) = base.split_once("://")?;
+    let (authority, base_path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+
+    let reference = reference.trim();
+    let reference = reference.split('#').next().unwrap_or(reference);
+    if reference.is_empty() {
+        return None;
+    }
+
+    let (target_authority, path) = if let Some((target_scheme, target)) = reference.split_once("://").filter(|(s, _)| !s.contains('/')) {
+        if !target_scheme.eq_ignore_ascii_case(scheme) {
+            return None;
+        }
+        match target.find('/') {
+            Some(index) => (&target[..index], target[index..].to_string()),
+            None => (target, "/".to_string()),
+        }
+    } else if let Some(target) = reference.strip_prefix("//") {
+        match target.find('/') {
+            Some(index) => (&target[..index], target[index..].to_string()),
+            None => (target, "/".to_string()),
+        }
+    } else if reference.starts_with('/') {
+        (authority, reference.to_string())
+    } else {
+        // Relative to the directory of the request path
+        let base_path = base_path.split('?').next().unwrap_or(base_path);
+        let directory = &base_path[..base_path.rfind('/').map(|index| index + 1).unwrap_or(0)];
+        (authority, format!("{}{}", directory, reference))
+    };
+
+    let default_port = if scheme.eq_ignore_ascii_case("https") { ":443" } else { ":80" };
+    let normalize = |authority: &str| {
+        let authority = authority.to_lowercase();
+        authority.strip_suffix(default_port).map(str::to_string).unwrap_or(authority)
+    };
+    if normalize(target_authority) != normalize(authority) {
+        return None;
+    }
+    // Same spelling of the host as the request, so it matches the cache key
+    Some(format!("{}://{}{}", scheme, authority, path))
+}
 
+
+/// Matches text against a glob pattern
+/// # Parameters
+/// * pattern - `*` matches any run of characters, `?` a single one, everything else itself
+/// * text - text to match as a whole
+///
+/// - returns bool
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star_p;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+
+/// Adds headers to a raw HTTP message, replacing existing headers with the same name
+/// # Parameters
+/// * message - raw request or response bytes (start line, headers, body)
+/// * extra_headers - (name, value) pairs to add after the start line
+///
+/// - returns Vec<u8> with the modified message
+pub fn add_headers(message: &[u8], extra_headers: &[(&str, String)]) -> Vec<u8> {
+    let header_end = match message.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => position,
+        None => return message.to_vec(),
+    };
+    let head = String::from_utf8_lossy(&message[..header_end]);
+    let mut lines = head.split("\r\n");
+
+    let mut new_head = lines.next().unwrap_or("").to_string();
+    for (name, value) in extra_headers {
+        new_head.push_str(&format!("\r\n{}: {}", name, value));
+    }
+    for line in lines {
+        let replaced = line
+            .split_once(':')
+            .map(|(name, _)| extra_headers.iter().any(|(extra, _)| name.trim().eq_ignore_ascii_case(extra)))
+            .unwrap_or(false);
+        if !replaced {
+            new_head.push_str("\r\n");
+            new_head.push_str(line);
+        }
+    }
+
+    let mut modified = new_head.into_bytes();
+    modified.extend_from_slice(&message[header_end..]);
+    modified
+}
+
+
+/// Removes headers from a raw HTTP message
+/// # Parameters
+/// * message - raw request or response bytes (start line, headers, body)
+/// * names - header names to remove, in any case
+///
+/// - returns Vec<u8> with the modified message
+pub fn strip_headers(message: &[u8], names: &[&str]) -> Vec<u8> {
+    let header_end = match message.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => position,
+        None => return message.to_vec(),
+    };
+    let head = String::from_utf8_lossy(&message[..header_end]);
+
+    let kept: Vec<&str> = head
+        .split("\r\n")
+        .enumerate()
+        .filter(|(index, line)| {
+            *index == 0
+                || !line
+                    .split_once(':')
+                    .map(|(name, _)| names.iter().any(|removed| name.trim().eq_ignore_ascii_case(removed)))
+                    .unwrap_or(false)
+        })
+        .map(|(_, line)| line)
+        .collect();
+
+    let mut modified = kept.join("\r\n").into_bytes();
+    modified.extend_from_slice(&message[header_end..]);
+    modified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn glob_patterns() {
+        let cases = [
+            ("", "", true),
+            ("", "a", false),
+            ("*", "", true),
+            ("*", "anything", true),
+            ("http://*/img/*.png", "http://example.com/img/logo.png", true),
+            ("http://*/img/*.png", "http://example.com/img/logo.jpg", false),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("*ab", "aaab", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("**", "x", true),
+            ("é?", "éa", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern, text), expected, "{:?} against {:?}", pattern, text);
+        }
+    }
 }
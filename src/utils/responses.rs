@@ -1,27 +1,252 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
 use std::io::Write;
-use std::net::TcpStream;
+use std::time::SystemTime;
 
-/// Module for HTTP responses, like 403, I had to do more than just 403, but no time (I was sick)
+// Module for the responses generated by roxy itself (errors, block page)
 
-/// Sends a generic HTTP response
-fn send_response(client_stream: &mut TcpStream, status_line: &str, body: &str) {
-    let response = format!(
-        "HTTP/1.1 {}\r\n\
-        Content-Length: {}\r\n\
+/// Value of the `Via` header added to generated responses
+pub const VIA: &str = "1.1 roxy";
+
+/// Status codes roxy can answer with on its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Status {
+    BadRequest,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    ProxyAuthenticationRequired,
+    RequestTimeout,
+    PayloadTooLarge,
+    UriTooLong,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+}
+
+impl Status {
+    /// Numeric status code
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::BadRequest => 400,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::ProxyAuthenticationRequired => 407,
+            Status::RequestTimeout => 408,
+            Status::PayloadTooLarge => 413,
+            Status::UriTooLong => 414,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::BadGateway => 502,
+            Status::ServiceUnavailable => 503,
+            Status::GatewayTimeout => 504,
+        }
+    }
+
+    /// Reason phrase for the status line
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::BadRequest => "Bad Request",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            Status::RequestTimeout => "Request Timeout",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::BadGateway => "Bad Gateway",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::GatewayTimeout => "Gateway Timeout",
+        }
+    }
+
+    /// Looks up a status by its numeric code
+    pub fn from_code(code: u16) -> Option<Status> {
+        ALL_STATUSES.iter().copied().find(|status| status.code() == code)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+const ALL_STATUSES: [Status; 14] = [
+    Status::BadRequest,
+    Status::Forbidden,
+    Status::NotFound,
+    Status::MethodNotAllowed,
+    Status::ProxyAuthenticationRequired,
+    Status::RequestTimeout,
+    Status::PayloadTooLarge,
+    Status::UriTooLong,
+    Status::TooManyRequests,
+    Status::RequestHeaderFieldsTooLarge,
+    Status::InternalServerError,
+    Status::BadGateway,
+    Status::ServiceUnavailable,
+    Status::GatewayTimeout,
+];
+
+/// HTML templates for error bodies, with `{{status}}`, `{{reason}}` and `{{message}}` placeholders
+#[derive(Default)]
+pub struct ErrorTemplates {
+    /// Templates for specific status codes
+    by_status: HashMap<u16, String>,
+
+    /// Template used for every status without its own template
+    fallback: Option<String>,
+}
+
+impl ErrorTemplates {
+    /// Loads a template file for one status code, or for all of them when `status` is None
+    pub fn load(&mut self, status: Option<Status>, path: &str) -> io::Result<()> {
+        let template = fs::read_to_string(path)?;
+        match status {
+            Some(status) => {
+                self.by_status.insert(status.code(), template);
+            },
+            None => self.fallback = Some(template),
+        }
+        Ok(())
+    }
+
+    fn get(&self, status: Status) -> Option<&String> {
+        self.by_status.get(&status.code()).or(self.fallback.as_ref())
+    }
+}
+
+/// An error produced by roxy itself, rendered the same way wherever it comes from
+///
+/// Handler stages return `Err(ErrorResponse)` and the connection handler sends it
+#[derive(Debug)]
+pub struct ErrorResponse {
+    pub status: Status,
+
+    /// Human readable explanation, also used for the plain text body
+    pub message: String,
+
+    /// Extra headers (e.g. `Allow`, `Retry-After`)
+    pub headers: Vec<(String, String)>,
+
+    /// Pre-rendered body with its content type, bypasses templates
+    pub body: Option<(String, String)>,
+}
+
+impl ErrorResponse {
+    /// Creates an error response with the default message for the status
+    pub fn new(status: Status) -> Self {
+        ErrorResponse {
+            status,
+            message: status.reason().to_string(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    /// Replaces the message shown to the client
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Adds an extra header
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Uses an already rendered body instead of a template
+    pub fn with_body(mut self, content_type: &str, body: String) -> Self {
+        self.body = Some((content_type.to_string(), body));
+        self
+    }
+
+    /// Serializes the full response, picking the body from templates when none was set
+    pub fn to_bytes(&self, templates: &ErrorTemplates) -> Vec<u8> {
+        let (content_type, body) = match &self.body {
+            Some((content_type, body)) => (content_type.clone(), body.clone()),
+            None => match templates.get(self.status) {
+                Some(template) => ("text/html; charset=utf-8".to_string(), self.render_template(template)),
+                None => ("text/plain".to_string(), format!("{}\n", self.message)),
+            },
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 {}\r\n\
+            Date: {}\r\n\
+            Via: {}\r\n\
+            Content-Type: {}\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n",
+            self.status,
+            httpdate::fmt_http_date(SystemTime::now()),
+            VIA,
+            content_type,
+            body.len()
+        );
+        for (name, value) in &self.headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("\r\n");
+        response.push_str(&body);
+
+        response.into_bytes()
+    }
+
+    /// Writes the response to the client
+    pub fn send<W: Write>(&self, client_stream: &mut W, templates: &ErrorTemplates) {
+        let _ = client_stream.write_all(&self.to_bytes(templates));
+        let _ = client_stream.flush();
+    }
+
+    fn render_template(&self, template: &str) -> String {
+        template
+            .replace("{{status}}", &self.status.code().to_string())
+            .replace("{{reason}}", self.status.reason())
+            .replace("{{message}}", &escape_html(&self.message))
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+/// Plain text `200 OK` response for requests roxy answers itself (e.g. PURGE)
+pub fn ok_response(body: &str) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 200 OK\r\n\
+        Date: {}\r\n\
+        Via: {}\r\n\
         Content-Type: text/plain\r\n\
-        Connection: close\r\n\
-        \r\n\
-        {}",
-        status_line,
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        httpdate::fmt_http_date(SystemTime::now()),
+        VIA,
         body.len(),
         body
-    );
-
-    let _ = client_stream.write_all(response.as_bytes());
-    let _ = client_stream.flush();
+    )
+    .into_bytes()
 }
 
-/// Sends a `403 Forbidden` response
-pub fn send_403_forbidden(client_stream: &mut TcpStream) {
-    send_response(client_stream, "403 Forbidden", "Access Denied: Blacklisted");
+/// Escapes text for safe inclusion in HTML
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
 }
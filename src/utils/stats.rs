@@ -0,0 +1,212 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// module for aggregate proxy statistics (tunnels, upstream failures)
+
+/// Why a tunnel was torn down
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Client sent EOF
+    ClientClosed,
+    /// Server sent EOF
+    ServerClosed,
+    /// Client connection was reset or the pipe broke
+    ClientReset,
+    /// Server connection was reset or the pipe broke
+    ServerReset,
+    /// Any other I/O error
+    Error(String),
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::ClientClosed => write!(f, "client closed"),
+            CloseReason::ServerClosed => write!(f, "server closed"),
+            CloseReason::ClientReset => write!(f, "client reset"),
+            CloseReason::ServerReset => write!(f, "server reset"),
+            CloseReason::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+/// Accounting record emitted once per HTTPS tunnel
+#[derive(Clone, Debug)]
+pub struct TunnelRecord {
+    /// Target host with port
+    pub host: String,
+
+    /// Client address
+    pub client: String,
+
+    /// Bytes sent from client to server
+    pub bytes_up: u64,
+
+    /// Bytes sent from server to client
+    pub bytes_down: u64,
+
+    /// Time from tunnel establishment until both directions finished
+    pub duration: Duration,
+
+    /// Time from tunnel establishment until the first byte from the server
+    pub time_to_first_byte: Option<Duration>,
+
+    /// Which side ended the tunnel first
+    pub close_reason: CloseReason,
+}
+
+/// Running totals over all finished tunnels
+#[derive(Clone, Debug, Default)]
+pub struct TunnelTotals {
+    pub tunnels: u64,
+    pub active: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub total_duration: Duration,
+    pub closed_by_client: u64,
+    pub closed_by_server: u64,
+    pub closed_by_error: u64,
+}
+
+/// Kinds of upstream failures that are counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamFailure {
+    DnsTimeout,
+    DnsError,
+    ConnectTimeout,
+    ConnectError,
+    FirstByteTimeout,
+    TotalTimeout,
+    IoError,
+}
+
+/// Upstream failure counters
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamTotals {
+    pub dns_timeouts: u64,
+    pub dns_errors: u64,
+    pub connect_timeouts: u64,
+    pub connect_errors: u64,
+    pub first_byte_timeouts: u64,
+    pub total_timeouts: u64,
+    pub io_errors: u64,
+    pub retries: u64,
+}
+
+/// Locks the totals, a panic in another handler thread doesn't make them unusable
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Keeps a tunnel in the active count, including when it ends with an error
+pub struct ActiveTunnel<'a> {
+    stats: &'a ProxyStats,
+}
+
+impl Drop for ActiveTunnel<'_> {
+    fn drop(&mut self) {
+        let mut tunnels = lock(&self.stats.tunnels);
+        tunnels.active = tunnels.active.saturating_sub(1);
+    }
+}
+
+/// Thread-safe aggregate statistics shared by all connection handlers
+pub struct ProxyStats {
+    tunnels: Arc<Mutex<TunnelTotals>>,
+    upstream: Arc<Mutex<UpstreamTotals>>,
+}
+
+impl ProxyStats {
+    pub fn new() -> Self {
+        Self {
+            tunnels: Arc::new(Mutex::new(TunnelTotals::default())),
+            upstream: Arc::new(Mutex::new(UpstreamTotals::default())),
+        }
+    }
+
+    /// Marks a tunnel as established, it counts as active until the returned guard is dropped
+    pub fn tunnel_opened(&self) -> ActiveTunnel<'_> {
+        let mut tunnels = lock(&self.tunnels);
+        tunnels.active += 1;
+        ActiveTunnel { stats: self }
+    }
+
+    /// Adds a finished tunnel to the totals
+    pub fn record_tunnel(&self, record: &TunnelRecord) {
+        let mut tunnels = lock(&self.tunnels);
+        tunnels.tunnels += 1;
+        tunnels.bytes_up += record.bytes_up;
+        tunnels.bytes_down += record.bytes_down;
+        tunnels.total_duration += record.duration;
+
+        match record.close_reason {
+            CloseReason::ClientClosed => tunnels.closed_by_client += 1,
+            CloseReason::ServerClosed => tunnels.closed_by_server += 1,
+            _ => tunnels.closed_by_error += 1,
+        }
+    }
+
+    /// Snapshot of the tunnel totals
+    pub fn tunnel_totals(&self) -> TunnelTotals {
+        lock(&self.tunnels).clone()
+    }
+
+    /// Counts a failed attempt to reach an upstream server
+    pub fn record_upstream_failure(&self, failure: UpstreamFailure) {
+        let mut upstream = lock(&self.upstream);
+        match failure {
+            UpstreamFailure::DnsTimeout => upstream.dns_timeouts += 1,
+            UpstreamFailure::DnsError => upstream.dns_errors += 1,
+            UpstreamFailure::ConnectTimeout => upstream.connect_timeouts += 1,
+            UpstreamFailure::ConnectError => upstream.connect_errors += 1,
+            UpstreamFailure::FirstByteTimeout => upstream.first_byte_timeouts += 1,
+            UpstreamFailure::TotalTimeout => upstream.total_timeouts += 1,
+            UpstreamFailure::IoError => upstream.io_errors += 1,
+        }
+    }
+
+    /// Counts a retried upstream request
+    pub fn record_upstream_retry(&self) {
+        lock(&self.upstream).retries += 1;
+    }
+
+    /// Snapshot of the upstream failure counters
+    pub fn upstream_totals(&self) -> UpstreamTotals {
+        lock(&self.upstream).clone()
+    }
+
+    /// Display aggregate statistics
+    pub fn print_summary(&self) {
+        let tunnels = self.tunnel_totals();
+        println!("📊 Tunnels:");
+        println!(" - finished: {} (active: {})", tunnels.tunnels, tunnels.active);
+        println!(" - bytes up: {}, bytes down: {}", tunnels.bytes_up, tunnels.bytes_down);
+        if tunnels.tunnels > 0 {
+            println!(" - average duration: {:.2?}", Duration::from_secs_f64(tunnels.total_duration.as_secs_f64() / tunnels.tunnels as f64));
+        }
+        println!(
+            " - closed by client: {}, by server: {}, by error: {}",
+            tunnels.closed_by_client, tunnels.closed_by_server, tunnels.closed_by_error
+        );
+
+        let upstream = self.upstream_totals();
+        println!("📊 Upstream failures:");
+        println!(" - DNS: {} timeouts, {} errors", upstream.dns_timeouts, upstream.dns_errors);
+        println!(" - connect: {} timeouts, {} errors", upstream.connect_timeouts, upstream.connect_errors);
+        println!(
+            " - response: {} first byte timeouts, {} total timeouts, {} I/O errors",
+            upstream.first_byte_timeouts, upstream.total_timeouts, upstream.io_errors
+        );
+        println!(" - retries: {}", upstream.retries);
+    }
+}
+
+impl Default for ProxyStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}